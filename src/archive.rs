@@ -0,0 +1,259 @@
+use crate::provider::{ProvidedSources, SourceProvider};
+use dir_view::DirView;
+use std::{
+    collections::BTreeMap,
+    io::{self, Read as _},
+    path::{Component, Path, PathBuf},
+};
+
+/// The archive formats supported by [ArchiveSources].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    /// A gzip compressed tar archive.
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Guesses the format from the extension of `path`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Errors reading an archive.
+#[derive(thiserror::Error, Debug)]
+pub enum ArchiveError {
+    #[error("Io error {0} ")]
+    Io(#[from] io::Error),
+    #[error("Zip error {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("Unknown archive format for {}", .0.display())]
+    UnknownFormat(PathBuf),
+    /// The archive, or the files within it, are larger than the size limit.
+    #[error("Archive {} is larger than the limit of {limit} bytes", .archive.display())]
+    TooLarge { archive: PathBuf, limit: u64 },
+    /// An entry whose path is absolute or contains `..`, and so would escape the archive.
+    #[error(
+        "Archive {} contains the entry {} outside of the archive",
+        .archive.display(),
+        .entry.display()
+    )]
+    PathTraversal { archive: PathBuf, entry: PathBuf },
+}
+
+/// A `SourceProvider` for the files within a tar or zip archive.
+///
+/// Sources are named by the path of the archive and the path of the entry
+/// separated by `!/`, e.g. `bundle.tar!/src/grammar.y`, so that diagnostics
+/// point inside the archive. The whole archive is read when it is opened,
+/// so both the archive and the total size of its files are limited to
+/// [DEFAULT_SIZE_LIMIT](Self::DEFAULT_SIZE_LIMIT) bytes unless a limit is given.
+///
+/// Reading an archive containing an entry whose path is absolute or
+/// contains `..` fails with `ArchiveError::PathTraversal`.
+#[derive(Debug)]
+pub struct ArchiveSources {
+    archive: PathBuf,
+    /// The path of the archive within the dir it was opened from.
+    file: Option<PathBuf>,
+    entries: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl ArchiveSources {
+    /// The size limit in bytes of the constructors which don't take one.
+    pub const DEFAULT_SIZE_LIMIT: u64 = 256 * 1024 * 1024;
+
+    /// Reads the archive at `path` within `dir`, guessing the format from its extension.
+    pub fn open(dir: &DirView, path: impl Into<PathBuf>) -> Result<Self, ArchiveError> {
+        let path = path.into();
+        let format =
+            ArchiveFormat::from_path(&path).ok_or(ArchiveError::UnknownFormat(path.clone()))?;
+        Self::open_with_format(dir, path, format)
+    }
+
+    /// Reads the archive at `path` within `dir`.
+    pub fn open_with_format(
+        dir: &DirView,
+        path: impl Into<PathBuf>,
+        format: ArchiveFormat,
+    ) -> Result<Self, ArchiveError> {
+        Self::open_with_limit(dir, path, format, Self::DEFAULT_SIZE_LIMIT)
+    }
+
+    /// Reads the archive at `path` within `dir`, failing with `ArchiveError::TooLarge`
+    /// if the archive or the files within it are larger than `limit` bytes.
+    pub fn open_with_limit(
+        dir: &DirView,
+        path: impl Into<PathBuf>,
+        format: ArchiveFormat,
+        limit: u64,
+    ) -> Result<Self, ArchiveError> {
+        let path = path.into();
+        let bytes = read_limited(&path, dir.open(&path)?, limit)?;
+        let mut archive = Self::from_bytes_with_limit(path.clone(), &bytes, format, limit)?;
+        archive.file = Some(path);
+        Ok(archive)
+    }
+
+    /// Reads an archive from memory, `archive` is the name sources are displayed under.
+    pub fn from_bytes(
+        archive: impl Into<PathBuf>,
+        bytes: &[u8],
+        format: ArchiveFormat,
+    ) -> Result<Self, ArchiveError> {
+        Self::from_bytes_with_limit(archive, bytes, format, Self::DEFAULT_SIZE_LIMIT)
+    }
+
+    /// Reads an archive from memory, failing with `ArchiveError::TooLarge`
+    /// if the files within it are larger than `limit` bytes in total.
+    pub fn from_bytes_with_limit(
+        archive: impl Into<PathBuf>,
+        bytes: &[u8],
+        format: ArchiveFormat,
+        limit: u64,
+    ) -> Result<Self, ArchiveError> {
+        let mut sources = Self {
+            archive: archive.into(),
+            file: None,
+            entries: BTreeMap::new(),
+        };
+        let mut remaining = limit;
+        match format {
+            ArchiveFormat::Tar => sources.read_tar(bytes, &mut remaining)?,
+            ArchiveFormat::TarGz => {
+                sources.read_tar(flate2::read::GzDecoder::new(bytes), &mut remaining)?
+            }
+            ArchiveFormat::Zip => {
+                let mut zip = zip::ZipArchive::new(io::Cursor::new(bytes))?;
+                for idx in 0..zip.len() {
+                    let file = zip.by_index(idx)?;
+                    if file.is_dir() {
+                        continue;
+                    }
+                    let entry = PathBuf::from(file.name());
+                    sources.insert(&entry, file, &mut remaining)?;
+                }
+            }
+        }
+        Ok(sources)
+    }
+
+    fn read_tar(&mut self, reader: impl io::Read, remaining: &mut u64) -> Result<(), ArchiveError> {
+        let mut tar = tar::Archive::new(reader);
+        for file in tar.entries()? {
+            let file = file?;
+            if !file.header().entry_type().is_file() {
+                continue;
+            }
+            let entry = file.path()?.into_owned();
+            self.insert(&entry, file, remaining)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the contents of `entry` from `file`, failing if its path escapes the archive.
+    fn insert(
+        &mut self,
+        entry: &Path,
+        file: impl io::Read,
+        remaining: &mut u64,
+    ) -> Result<(), ArchiveError> {
+        let normalized = entry_path(entry).ok_or_else(|| ArchiveError::PathTraversal {
+            archive: self.archive.clone(),
+            entry: entry.to_path_buf(),
+        })?;
+        let contents = read_limited(&self.archive, file, *remaining)?;
+        *remaining -= contents.len() as u64;
+        self.entries.insert(normalized, contents);
+        Ok(())
+    }
+
+    /// The path of every file in the archive, in sorted order.
+    pub fn entries(&self) -> impl Iterator<Item = &Path> {
+        self.entries.keys().map(PathBuf::as_path)
+    }
+
+    /// The name of the source for the archive entry `entry`.
+    pub fn source_path(&self, entry: &Path) -> PathBuf {
+        PathBuf::from(format!("{}!/{}", self.archive.display(), entry.display()))
+    }
+
+    /// Loads the entries of the archive selected by `filter`, in sorted order.
+    pub fn select(self, filter: impl Fn(&Path) -> bool) -> ProvidedSources {
+        let paths = self
+            .entries()
+            .filter(|entry| filter(entry))
+            .map(|entry| self.source_path(entry))
+            .collect::<Vec<_>>();
+        ProvidedSources {
+            provider: Box::new(self),
+            paths,
+        }
+    }
+}
+
+impl SourceProvider for ArchiveSources {
+    fn read_source(&mut self, path: &Path) -> io::Result<String> {
+        let prefix = format!("{}!/", self.archive.display());
+        let contents = path
+            .to_str()
+            .and_then(|path| path.strip_prefix(&prefix))
+            .and_then(|entry| self.entries.get(Path::new(entry)))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No source for {}", path.display()),
+                )
+            })?;
+        String::from_utf8(contents.clone())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// The path of the archive, if it was opened from a dir rather than from memory.
+    fn file_path(&self, _path: &Path) -> Option<PathBuf> {
+        self.file.clone()
+    }
+}
+
+/// Reads all of `reader`, failing with `ArchiveError::TooLarge` if it is longer than `limit`.
+fn read_limited(
+    archive: &Path,
+    reader: impl io::Read,
+    limit: u64,
+) -> Result<Vec<u8>, ArchiveError> {
+    let mut contents = vec![];
+    reader
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut contents)?;
+    if contents.len() as u64 > limit {
+        return Err(ArchiveError::TooLarge {
+            archive: archive.to_path_buf(),
+            limit,
+        });
+    }
+    Ok(contents)
+}
+
+/// Normalizes the path of an archive entry, returning `None` if it escapes the archive.
+fn entry_path(entry: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in entry.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::CurDir => (),
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(normalized)
+}
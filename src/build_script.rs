@@ -0,0 +1,111 @@
+use crate::{
+    diagnostics::{
+        buffered_diagnostics, BufferedDiagnostics, Diagnostic, DiagnosticsEnv, Severity,
+    },
+    render::Renderer,
+    tool::Tool,
+};
+use std::io;
+
+/// A `Diagnostics` implementation for tools run from a cargo build script.
+///
+/// Cargo only displays the output of a successful build script when it is
+/// given as `cargo:warning=` lines, so every line of each diagnostic rendered
+/// by an uncoloured [Renderer] is written as one, errors included. Diagnostics
+/// are buffered until `no_more_data`, which writes them followed by a summary
+/// line if there were errors among them, and flushes the writer. Each summary
+/// only counts the diagnostics written with it, so that diagnostics passed
+/// through multiple drivers don't repeat the failures of earlier ones.
+///
+/// Errors do not stop the build by themselves, build scripts should check
+/// [failure_summary](Self::failure_summary) and fail with it.
+pub struct BuildScriptDiagnostics<X: Tool, W: io::Write = io::Stdout> {
+    writer: W,
+    pending: Vec<Diagnostic<X>>,
+    error_count: usize,
+    warning_count: usize,
+}
+
+impl<X: Tool> BuildScriptDiagnostics<X, io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl<X: Tool, W: io::Write> BuildScriptDiagnostics<X, W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            pending: vec![],
+            error_count: 0,
+            warning_count: 0,
+        }
+    }
+    /// The number of errors, including denied warnings, written so far.
+    pub fn error_count(&self) -> usize {
+        self.error_count
+    }
+    /// The number of warnings written so far.
+    pub fn warning_count(&self) -> usize {
+        self.warning_count
+    }
+    /// A summary of the errors written so far, or `None` if there were none.
+    ///
+    /// e.g. `yacc failed with 2 errors and 1 warning`
+    pub fn failure_summary(&self) -> Option<String> {
+        failure_summary::<X>(self.error_count, self.warning_count)
+    }
+    pub fn writer(&self) -> &W {
+        &self.writer
+    }
+    pub fn into_writer(self) -> W {
+        self.writer
+    }
+}
+
+buffered_diagnostics!(BuildScriptDiagnostics);
+
+impl<X: Tool, W: io::Write> BufferedDiagnostics<X> for BuildScriptDiagnostics<X, W> {
+    fn pending(&mut self) -> &mut Vec<Diagnostic<X>> {
+        &mut self.pending
+    }
+    fn write_diagnostics(&mut self, diagnostics: Vec<Diagnostic<X>>, env: &DiagnosticsEnv<'_, X>) {
+        let renderer = Renderer::new(false);
+        let (mut error_count, mut warning_count) = (0, 0);
+        for diagnostic in diagnostics {
+            match diagnostic.severity() {
+                Severity::Error => error_count += 1,
+                Severity::Warning => warning_count += 1,
+            }
+            let mut rendered = vec![];
+            let _ = renderer.render_diagnostic(&diagnostic, env.source_cache(), &mut rendered);
+            for line in String::from_utf8_lossy(&rendered).lines() {
+                if !line.trim().is_empty() {
+                    let _ = writeln!(self.writer, "cargo:warning={line}");
+                }
+            }
+        }
+        self.error_count += error_count;
+        self.warning_count += warning_count;
+        if let Some(summary) = failure_summary::<X>(error_count, warning_count) {
+            let _ = writeln!(self.writer, "cargo:warning=error: {summary}");
+        }
+        let _ = self.writer.flush();
+    }
+}
+
+fn failure_summary<X: Tool>(error_count: usize, warning_count: usize) -> Option<String> {
+    if error_count == 0 {
+        return None;
+    }
+    let plural = |n: usize, noun: &str| match n {
+        1 => format!("1 {noun}"),
+        n => format!("{n} {noun}s"),
+    };
+    let mut summary = format!("{} failed with {}", X::name(), plural(error_count, "error"));
+    if warning_count != 0 {
+        summary.push_str(" and ");
+        summary.push_str(&plural(warning_count, "warning"));
+    }
+    Some(summary)
+}
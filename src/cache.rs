@@ -0,0 +1,532 @@
+use crate::{
+    default_impls::{DefaultDriver, DefaultDriverArgs, DefaultDriverOptionalArgs},
+    diagnostics::{Diagnostic, Diagnostics, DiagnosticsEnv},
+    driver::{
+        insert_sources, run_tool, CacheStatus, Driver, DriverError, DriverOutput, LoadedSource,
+        ToolRun,
+    },
+    encode::{Decode, DecodeError, Decoder, Encode, Encoder, StableHasher},
+    fix::Suggestion,
+    graph::{Dependency, DependencyKind},
+    incremental::{replay, IncrementalState},
+    lint::LintLevels,
+    output::write_atomic,
+    provider::SourceProvider,
+    source::{Session, SourceCache, SourceId},
+    tool::Tool,
+    DefaultDriverEnv, Params,
+};
+use dir_view::{cap_std::fs::OpenOptions, DirView};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Written at the start of every entry, changed whenever the entry format changes.
+const ENTRY_MAGIC: &[u8] = b"driver cache 2\n";
+
+/// A cache of the outputs and diagnostics of the tool `X` kept in a directory,
+/// shared between processes.
+///
+/// Entries are keyed by the version of this crate, the tool's name and `VERSION`,
+/// the tool arguments and lint levels, and the path and text of every loaded source,
+/// which are hashed with SHA-256. Entries also record the path and digest of every
+/// loaded source, and the text of every included source, which are compared before
+/// an entry is used. Entries are stored as `<tool name>/<key>` within `dir`, which
+/// should be writable, failing to write an entry does not fail the run.
+///
+/// The output, diagnostics and source kinds may only refer to sources of the session,
+/// entries which refer to other sources are never hit.
+pub struct DiskCache<X: Tool> {
+    dir: DirView,
+    codec: ToolCodec<X>,
+}
+
+/// Functions for the tool of a `DiskCache`, so that the driver does not require their bounds.
+struct ToolCodec<X: Tool> {
+    hash_args: fn(&(X::RequiredArgs, X::OptionalArgs), &mut StableHasher),
+    encode_error: fn(&X::Error, &mut Encoder),
+    encode_warning: fn(&X::Warning, &mut Encoder),
+    encode_kind: fn(&X::SourceKind, &mut Encoder),
+    encode_output: fn(&X::Output, &mut Encoder),
+    decode_error: fn(&mut Decoder<'_>) -> Result<X::Error, DecodeError>,
+    decode_warning: fn(&mut Decoder<'_>) -> Result<X::Warning, DecodeError>,
+    decode_kind: fn(&mut Decoder<'_>) -> Result<X::SourceKind, DecodeError>,
+    decode_output: fn(&mut Decoder<'_>) -> Result<X::Output, DecodeError>,
+}
+
+impl<X: Tool> DiskCache<X> {
+    /// A cache in `dir` for runs of the tool `X`.
+    pub fn new(dir: DirView) -> Self
+    where
+        X::RequiredArgs: Hash,
+        X::OptionalArgs: Hash,
+        X::Error: Encode + Decode,
+        X::Warning: Encode + Decode,
+        X::SourceKind: Encode + Decode,
+        X::Output: Encode + Decode,
+    {
+        let codec = ToolCodec::<X> {
+            hash_args: |args, hasher| args.hash(hasher),
+            encode_error: X::Error::encode,
+            encode_warning: X::Warning::encode,
+            encode_kind: X::SourceKind::encode,
+            encode_output: X::Output::encode,
+            decode_error: X::Error::decode,
+            decode_warning: X::Warning::decode,
+            decode_kind: X::SourceKind::decode,
+            decode_output: X::Output::decode,
+        };
+        Self { dir, codec }
+    }
+
+    /// Removes the entries of every tool which have not been written or hit within `max_age`.
+    ///
+    /// Returns the number of entries removed.
+    pub fn prune(&self, max_age: Duration) -> io::Result<usize> {
+        let now = SystemTime::now();
+        let mut removed = 0;
+        for tool_dir in self.dir.entries()? {
+            let tool_dir = tool_dir?;
+            if !tool_dir.file_type()?.is_dir() {
+                continue;
+            }
+            for entry in tool_dir.open_dir()?.entries()? {
+                let entry = entry?;
+                let modified = entry.metadata()?.modified()?.into_std();
+                if now.duration_since(modified).unwrap_or_default() >= max_age {
+                    entry.remove_file()?;
+                    removed += 1;
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    /// The path of the entry for a run of `X`.
+    fn entry_path(
+        &self,
+        tool_args: &(X::RequiredArgs, X::OptionalArgs),
+        lint_levels: &LintLevels,
+        loaded: &[LoadedSource],
+    ) -> PathBuf {
+        let mut hasher = StableHasher::new();
+        // The entry format, and how tool types are encoded, may change between versions.
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        X::name().hash(&mut hasher);
+        X::VERSION.hash(&mut hasher);
+        (self.codec.hash_args)(tool_args, &mut hasher);
+        lint_levels.hash(&mut hasher);
+        for source in loaded {
+            source.path.hash(&mut hasher);
+            source.src.hash(&mut hasher);
+        }
+        Path::new(X::name()).join(hex(&hasher.digest()))
+    }
+
+    /// Restores the run from the cache entry if there is a current one,
+    /// otherwise runs the tool and writes its entry.
+    pub(crate) fn run<D: Diagnostics<X>>(
+        &self,
+        tool_run: ToolRun<X>,
+        source_cache: &mut SourceCache,
+        diagnostics: &mut D,
+    ) -> Result<DriverOutput<X>, DriverError> {
+        let ToolRun {
+            tool,
+            tool_args,
+            loaded,
+            mut include_provider,
+            lint_levels,
+        } = tool_run;
+        let codec = &self.codec;
+        let path = self.entry_path(&tool_args, &lint_levels, &loaded);
+        let bytes = match self.dir.read(&path) {
+            Ok(bytes) => Some(bytes),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let mut session = insert_sources(source_cache, loaded);
+        let entry = bytes.as_deref().and_then(|bytes| Entry::parse(bytes).ok());
+        if let Some(entry) = entry.filter(|entry| entry.is_current(include_provider.as_mut())) {
+            if let Ok(output) = entry.restore(codec, &mut session, source_cache, diagnostics) {
+                self.touch(&path);
+                return Ok(DriverOutput {
+                    output,
+                    session,
+                    cache_status: Some(CacheStatus::Hit),
+                    cache_warning: None,
+                });
+            }
+        }
+        let mut encoding = EncodingDiagnostics {
+            inner: diagnostics,
+            codec,
+            encoder: Encoder::new(),
+        };
+        let (output, session) = run_tool(
+            tool,
+            Params::from(tool_args),
+            lint_levels,
+            include_provider,
+            session,
+            source_cache,
+            &mut encoding,
+        );
+        let diagnostics = encoding.encoder.into_bytes();
+        let entry = Entry::encode(codec, &output, &session, source_cache, &diagnostics);
+        let cache_warning = write_atomic(&self.dir, &path, &entry)
+            .err()
+            .map(|source| DriverError::Write { path, source });
+        Ok(DriverOutput {
+            output,
+            session,
+            cache_status: Some(CacheStatus::Miss),
+            cache_warning,
+        })
+    }
+
+    /// Marks the entry at `path` as recently used for `prune`, if the cache is writable.
+    fn touch(&self, path: &Path) {
+        let file = self.dir.open_with(path, OpenOptions::new().write(true));
+        if let Ok(file) = file {
+            let _ = file.into_std().set_modified(SystemTime::now());
+        }
+    }
+}
+
+impl<X> Driver<X, DefaultDriver>
+where
+    X: Tool,
+    (DefaultDriverArgs, DefaultDriverOptionalArgs): Into<Params<DefaultDriver>>,
+    (X::RequiredArgs, X::OptionalArgs): Into<Params<X>>,
+{
+    /// Like `driver_init`, but restores the run from `cache` rather than calling
+    /// `tool_init` if it has a current entry, and otherwise writes one.
+    pub fn driver_init_cached<D: Diagnostics<X>>(
+        self,
+        driver_env: DefaultDriverEnv<'_, X, D>,
+        cache: &DiskCache<X>,
+    ) -> Result<DriverOutput<X>, DriverError> {
+        self.driver_init_with(driver_env, |tool_run, source_cache, diagnostics| {
+            cache.run(tool_run, source_cache, diagnostics)
+        })
+    }
+}
+
+impl<X: Tool> IncrementalState<X> {
+    /// Consults `cache` when the previous run can't be reused.
+    pub fn with_disk_cache(mut self, cache: DiskCache<X>) -> Self {
+        self.disk_cache = Some(cache);
+        self
+    }
+}
+
+/// The parts of a cache entry which do not depend on the tool.
+///
+/// Source ids are those of the run which wrote the entry, they are mapped to the
+/// ids of the restored sources when the remaining parts are decoded.
+struct Entry {
+    /// Each loaded source with its path and the digest of its text.
+    loaded: Vec<(u64, PathBuf, Vec<u8>)>,
+    included: Vec<(u64, PathBuf, String)>,
+    /// Each source added by the tool with its encoded `SourceKind`.
+    added: Vec<(u64, PathBuf, String, Vec<u8>)>,
+    dependencies: Vec<(u64, u64, u8)>,
+    file_dependencies: Vec<PathBuf>,
+    suggestions: Vec<(u64, Vec<Suggestion>)>,
+    diagnostics: Vec<u8>,
+    output: Vec<u8>,
+}
+
+impl Entry {
+    fn encode<X: Tool>(
+        codec: &ToolCodec<X>,
+        output: &X::Output,
+        session: &Session<X::SourceKind>,
+        source_cache: &SourceCache,
+        diagnostics: &[u8],
+    ) -> Vec<u8> {
+        let raw = |src_id: &SourceId| src_id.raw();
+        let source = |src_id: &SourceId| {
+            let path = source_cache.path_for_id(*src_id).unwrap_or(Path::new(""));
+            let src = source_cache.source_for_id(*src_id).unwrap_or_default();
+            (raw(src_id), path.to_path_buf(), src.to_string())
+        };
+        let mut encoder = Encoder::new();
+        for byte in ENTRY_MAGIC {
+            encoder.write_u8(*byte);
+        }
+        let loaded = session.loaded_source_ids().iter().map(|src_id| {
+            let (raw, path, src) = source(src_id);
+            (raw, path, content_digest(&src))
+        });
+        loaded.collect::<Vec<_>>().encode(&mut encoder);
+        let included = session.included_source_ids().iter().map(source);
+        included.collect::<Vec<_>>().encode(&mut encoder);
+        encoder.write_u64(session.added_source_ids().len() as u64);
+        for src_id in session.added_source_ids() {
+            source(src_id).encode(&mut encoder);
+            let mut kind = Encoder::new();
+            if let Some(source_kind) = session.source_kinds.get(src_id) {
+                (codec.encode_kind)(source_kind, &mut kind);
+            }
+            encoder.write_bytes(&kind.into_bytes());
+        }
+        encoder.write_u64(session.dependency_edges().len() as u64);
+        for edge in session.dependency_edges() {
+            raw(&edge.dependent).encode(&mut encoder);
+            raw(&edge.dependency).encode(&mut encoder);
+            encoder.write_u8(match edge.kind {
+                DependencyKind::Include => 0,
+                DependencyKind::Generated => 1,
+                DependencyKind::Fixed => 2,
+            });
+        }
+        session.file_dependencies().encode(&mut encoder);
+        let mut suggestions = session.suggestions.iter().collect::<Vec<_>>();
+        suggestions.sort_by_key(|(src_id, _)| **src_id);
+        encoder.write_u64(suggestions.len() as u64);
+        for (src_id, suggestions) in suggestions {
+            raw(src_id).encode(&mut encoder);
+            suggestions.encode(&mut encoder);
+        }
+        encoder.write_bytes(diagnostics);
+        let mut output_encoder = Encoder::new();
+        (codec.encode_output)(output, &mut output_encoder);
+        encoder.write_bytes(&output_encoder.into_bytes());
+        encoder.into_bytes()
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let no_ids = HashMap::new();
+        let bytes = bytes
+            .strip_prefix(ENTRY_MAGIC)
+            .ok_or(DecodeError::Invalid("not a cache entry"))?;
+        let mut decoder = Decoder::new(bytes, &no_ids);
+        let loaded = Vec::decode(&mut decoder)?;
+        let included = Vec::decode(&mut decoder)?;
+        let mut added = vec![];
+        for _ in 0..decoder.read_u64()? {
+            let (src_id, path, src) = Decode::decode(&mut decoder)?;
+            added.push((src_id, path, src, decoder.read_bytes()?.to_vec()));
+        }
+        let mut dependencies = vec![];
+        for _ in 0..decoder.read_u64()? {
+            let dependent = u64::decode(&mut decoder)?;
+            let dependency = u64::decode(&mut decoder)?;
+            dependencies.push((dependent, dependency, decoder.read_u8()?));
+        }
+        let file_dependencies = Vec::decode(&mut decoder)?;
+        let suggestions = Vec::decode(&mut decoder)?;
+        let diagnostics = decoder.read_bytes()?.to_vec();
+        let output = decoder.read_bytes()?.to_vec();
+        if !decoder.is_empty() {
+            return Err(DecodeError::Invalid("trailing data"));
+        }
+        Ok(Self {
+            loaded,
+            included,
+            added,
+            dependencies,
+            file_dependencies,
+            suggestions,
+            diagnostics,
+            output,
+        })
+    }
+
+    /// Whether the included sources are unchanged.
+    fn is_current(&self, include_provider: Option<&mut Box<dyn SourceProvider>>) -> bool {
+        if self.included.is_empty() {
+            return true;
+        }
+        let Some(provider) = include_provider else {
+            return false;
+        };
+        self.included
+            .iter()
+            .all(|(_, path, src)| provider.read_source(path).ok().as_ref() == Some(src))
+    }
+
+    /// Adds the sources of the entry to `source_cache` and `session`, replays the
+    /// diagnostics, and returns the output.
+    ///
+    /// `session` must contain only the loaded sources. If decoding fails, `session`
+    /// is left unchanged and the sources are removed from `source_cache` again.
+    fn restore<X: Tool, D: Diagnostics<X>>(
+        self,
+        codec: &ToolCodec<X>,
+        session: &mut Session<X::SourceKind>,
+        source_cache: &mut SourceCache,
+        diagnostics: &mut D,
+    ) -> Result<X::Output, DecodeError> {
+        if self.loaded.len() != session.loaded_source_ids().len() {
+            return Err(DecodeError::Invalid("loaded sources differ"));
+        }
+        let mut source_ids = HashMap::new();
+        for ((raw, path, digest), src_id) in self.loaded.iter().zip(session.loaded_source_ids()) {
+            let loaded_path = source_cache.path_for_id(*src_id);
+            let loaded_src = source_cache.source_for_id(*src_id).unwrap_or_default();
+            if loaded_path != Some(path.as_path()) || *digest != content_digest(loaded_src) {
+                return Err(DecodeError::Invalid("loaded sources differ"));
+            }
+            source_ids.insert(*raw, *src_id);
+        }
+        let mut included = vec![];
+        for (raw, path, src) in &self.included {
+            let src_id = source_cache.load(path.clone(), src.clone());
+            source_ids.insert(*raw, src_id);
+            included.push(src_id);
+        }
+        let mut added = vec![];
+        for (raw, path, src, _) in &self.added {
+            let src_id = source_cache.insert(path.clone(), src.clone());
+            source_ids.insert(*raw, src_id);
+            added.push(src_id);
+        }
+        let restored = match self.decode_restored(codec, &source_ids) {
+            Ok(restored) => restored,
+            Err(e) => {
+                for src_id in included.iter().chain(&added) {
+                    source_cache.release(*src_id);
+                }
+                return Err(e);
+            }
+        };
+        session.source_ids_from_includes = included;
+        for (src_id, kind) in added.into_iter().zip(restored.kinds) {
+            session.add_source_id(src_id, kind);
+        }
+        session.dependencies = restored.dependencies;
+        session.file_dependencies = self.file_dependencies;
+        session.suggestions = restored.suggestions;
+        let env = DiagnosticsEnv::new(source_cache, session);
+        replay(restored.diagnostics, diagnostics, &env);
+        Ok(restored.output)
+    }
+
+    /// Decodes the parts of the entry which refer to sources, once they are restored.
+    fn decode_restored<X: Tool>(
+        &self,
+        codec: &ToolCodec<X>,
+        source_ids: &HashMap<u64, SourceId>,
+    ) -> Result<Restored<X>, DecodeError> {
+        let map = |raw: &u64| {
+            source_ids
+                .get(raw)
+                .copied()
+                .ok_or(DecodeError::UnknownSourceId(*raw))
+        };
+        let kinds = self
+            .added
+            .iter()
+            .map(|(_, _, _, kind)| decode_all(kind, source_ids, codec.decode_kind))
+            .collect::<Result<Vec<_>, _>>()?;
+        let dependencies = self
+            .dependencies
+            .iter()
+            .map(|(dependent, dependency, kind)| {
+                Ok(Dependency {
+                    dependent: map(dependent)?,
+                    dependency: map(dependency)?,
+                    kind: match kind {
+                        0 => DependencyKind::Include,
+                        1 => DependencyKind::Generated,
+                        2 => DependencyKind::Fixed,
+                        tag => return Err(DecodeError::InvalidTag(*tag)),
+                    },
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let suggestions = self
+            .suggestions
+            .iter()
+            .map(|(src_id, suggestions)| Ok((map(src_id)?, suggestions.clone())))
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        let mut decoder = Decoder::new(&self.diagnostics, source_ids);
+        let mut diagnostics = vec![];
+        while !decoder.is_empty() {
+            diagnostics.push(match decoder.read_u8()? {
+                0 => Diagnostic::Error((codec.decode_error)(&mut decoder)?),
+                1 => Diagnostic::Warning((codec.decode_warning)(&mut decoder)?),
+                2 => Diagnostic::DeniedWarning((codec.decode_warning)(&mut decoder)?),
+                tag => return Err(DecodeError::InvalidTag(tag)),
+            });
+        }
+        let output = decode_all(&self.output, source_ids, codec.decode_output)?;
+        Ok(Restored {
+            kinds,
+            dependencies,
+            suggestions,
+            diagnostics,
+            output,
+        })
+    }
+}
+
+/// The decoded parts of an [Entry] which refer to sources.
+struct Restored<X: Tool> {
+    /// The `SourceKind` of each added source.
+    kinds: Vec<X::SourceKind>,
+    dependencies: Vec<Dependency>,
+    suggestions: HashMap<SourceId, Vec<Suggestion>>,
+    diagnostics: Vec<Diagnostic<X>>,
+    output: X::Output,
+}
+
+/// The SHA-256 digest of `src`.
+fn content_digest(src: &str) -> Vec<u8> {
+    Sha256::digest(src).to_vec()
+}
+
+/// Formats `bytes` as lowercase hex.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Decodes a value which must take up all of `bytes`.
+fn decode_all<T>(
+    bytes: &[u8],
+    source_ids: &HashMap<u64, SourceId>,
+    decode: fn(&mut Decoder<'_>) -> Result<T, DecodeError>,
+) -> Result<T, DecodeError> {
+    let mut decoder = Decoder::new(bytes, source_ids);
+    let value = decode(&mut decoder)?;
+    match decoder.is_empty() {
+        true => Ok(value),
+        false => Err(DecodeError::Invalid("trailing data")),
+    }
+}
+
+/// Forwards diagnostics to another `Diagnostics`, encoding each for a cache entry.
+struct EncodingDiagnostics<'a, X: Tool, D> {
+    inner: &'a mut D,
+    codec: &'a ToolCodec<X>,
+    encoder: Encoder,
+}
+
+impl<X: Tool, D: Diagnostics<X>> Diagnostics<X> for EncodingDiagnostics<'_, X, D> {
+    fn emit_error(&mut self, e: X::Error) {
+        self.encoder.write_u8(0);
+        (self.codec.encode_error)(&e, &mut self.encoder);
+        self.inner.emit_error(e);
+    }
+    fn emit_warning(&mut self, w: X::Warning) {
+        self.encoder.write_u8(1);
+        (self.codec.encode_warning)(&w, &mut self.encoder);
+        self.inner.emit_warning(w);
+    }
+    fn emit_denied_warning(&mut self, w: X::Warning) {
+        self.encoder.write_u8(2);
+        (self.codec.encode_warning)(&w, &mut self.encoder);
+        self.inner.emit_denied_warning(w);
+    }
+    fn no_more_data(&mut self, env: &DiagnosticsEnv<'_, X>) {
+        self.inner.no_more_data(env);
+    }
+}
@@ -0,0 +1,17 @@
+use crate::tool::Tool;
+
+/// A code given by [Spanned::code](crate::Spanned::code) and its long form explanation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ErrorCode {
+    /// The code, e.g. `Y0042`.
+    pub code: &'static str,
+    /// A markdown explanation of the code, its causes and how to fix them.
+    pub explanation: &'static str,
+}
+
+/// Looks up the `ErrorCode` registered by `X` for `code`, ignoring ASCII case.
+pub fn lookup_error_code<X: Tool>(code: &str) -> Option<&'static ErrorCode> {
+    X::ERROR_CODES
+        .iter()
+        .find(|error_code| error_code.code.eq_ignore_ascii_case(code.trim()))
+}
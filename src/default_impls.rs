@@ -1,6 +1,10 @@
 use crate::{
     _unstable_api_,
     driver::{DriverOutput, DriverSelector, DriverTypes},
+    input::{InputSources, StdinSource},
+    lint::LintLevels,
+    output::OutputArgs,
+    provider::{MemorySources, ProvidedSources, SourceProvider},
     source::SourceCache,
     tool::Tool,
     Args,
@@ -23,6 +27,28 @@ pub struct DefaultDriverOptionalArgs {
     pub named_string: Option<(std::path::PathBuf, String)>,
     /// Takes an arbitrary `DirView`
     pub read_source: Option<(std::path::PathBuf, DirView)>,
+    /// Reads all of standard input, loaded after `read_source`.
+    pub read_stdin: Option<StdinSource>,
+    /// Sources loaded through a `SourceProvider`, loaded after `read_stdin`.
+    pub provided_sources: Vec<ProvidedSources>,
+    /// Files and glob patterns to read, loaded after `provided_sources`.
+    ///
+    /// Each `InputSources` is loaded in sorted order of its matched paths.
+    pub read_sources: Vec<InputSources>,
+    /// The level of each lint emitted by the tool.
+    pub lint_levels: LintLevels,
+    /// Unsaved buffers which are loaded in place of the files at their paths.
+    ///
+    /// Applies to every source the driver loads from a path, the `SourceCache`
+    /// retains the path the source was requested by.
+    pub overlay: MemorySources,
+    /// The provider for sources the tool includes through `ToolInitEnv::include`.
+    ///
+    /// Defaults to the `DirView` of `read_source`, or else of the first of `read_sources`.
+    /// The `overlay` is applied to it.
+    pub include_provider: Option<Box<dyn SourceProvider>>,
+    /// What to do with the sources added by the tool.
+    pub output: Option<OutputArgs>,
     #[doc(hidden)]
     pub _non_exhaustive: _unstable_api_::InternalDefault,
 }
@@ -64,7 +90,7 @@ impl<X: Tool> Default for SimpleDiagnostics<X> {
     }
 }
 
-use crate::diagnostics::Diagnostics;
+use crate::diagnostics::{Diagnostics, DiagnosticsEnv};
 
 impl<X: Tool> Diagnostics<X> for SimpleDiagnostics<X> {
     fn emit_error(&mut self, e: X::Error) {
@@ -73,7 +99,7 @@ impl<X: Tool> Diagnostics<X> for SimpleDiagnostics<X> {
     fn emit_warning(&mut self, w: X::Warning) {
         self.warnings.push(w);
     }
-    fn no_more_data(&mut self) {
+    fn no_more_data(&mut self, _env: &DiagnosticsEnv<'_, X>) {
         println!("no_more_data");
     }
 }
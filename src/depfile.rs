@@ -0,0 +1,85 @@
+use crate::source::Session;
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+/// `cargo:rerun-if-changed=` lines for every file in [Session::file_dependencies].
+///
+/// The dependencies are relative to the `DirView` they were read from, so each
+/// is joined to `base`, the path of that dir, e.g. `CARGO_MANIFEST_DIR`.
+/// Intended to be printed to stdout from a build script.
+pub fn rerun_if_changed<SourceKind>(session: &Session<SourceKind>, base: &Path) -> String {
+    let mut out = String::new();
+    for path in dependencies(session, base) {
+        writeln!(out, "cargo:rerun-if-changed={}", path.display()).unwrap();
+    }
+    out
+}
+
+/// A Make depfile with a rule for `target` depending on every file in
+/// [Session::file_dependencies].
+///
+/// Each dependency is joined to `base` as in [rerun_if_changed], and also gets
+/// an empty rule, so that Make does not fail when a dependency is deleted,
+/// as with `gcc -MP`.
+pub fn make_depfile<SourceKind>(
+    session: &Session<SourceKind>,
+    base: &Path,
+    target: &Path,
+) -> String {
+    let mut out = depfile_rule(session, base, target);
+    for path in dependencies(session, base) {
+        writeln!(out, "\n{}:", escape_make(&path)).unwrap();
+    }
+    out
+}
+
+/// A Ninja depfile for `target`, for use with the `depfile` rule variable.
+///
+/// Ninja only accepts a single rule, so unlike [make_depfile] there are no
+/// rules for the dependencies.
+pub fn ninja_depfile<SourceKind>(
+    session: &Session<SourceKind>,
+    base: &Path,
+    target: &Path,
+) -> String {
+    depfile_rule(session, base, target)
+}
+
+fn depfile_rule<SourceKind>(session: &Session<SourceKind>, base: &Path, target: &Path) -> String {
+    let mut out = format!("{}:", escape_make(target));
+    for path in dependencies(session, base) {
+        write!(out, " \\\n  {}", escape_make(&path)).unwrap();
+    }
+    out.push('\n');
+    out
+}
+
+/// The file dependencies of `session` joined to `base`.
+fn dependencies<'a, SourceKind>(
+    session: &'a Session<SourceKind>,
+    base: &'a Path,
+) -> impl Iterator<Item = PathBuf> + 'a {
+    session
+        .file_dependencies()
+        .iter()
+        .map(|path| base.join(path))
+}
+
+/// Escapes the characters of `path` which are special in a Make rule.
+fn escape_make(path: &Path) -> String {
+    let path = path.display().to_string();
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            ' ' | '#' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '$' => escaped.push_str("$$"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
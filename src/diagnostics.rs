@@ -1,4 +1,9 @@
+use crate::fix::Suggestion;
+use crate::lint::{LintLevel, LintLevels};
+use crate::source::{Session, SourceArtifact, SourceCache, SourceId};
 use crate::tool::{Tool, ToolError};
+use crate::{Span, Spanned};
+use std::{collections::HashMap, fmt};
 
 pub trait Diagnostics<X: Tool> {
     /// Indicatation that an error has occurred and the
@@ -7,7 +12,14 @@ pub trait Diagnostics<X: Tool> {
     /// Indicatation that a `warning` has occurred and the
     /// `Diagnostics` should take ownership of it.
     fn emit_warning(&mut self, warning: X::Warning);
-    /// Called by the `DiagnosticsEmitter` drop handler.
+    /// Indicatation that a `warning` denied by its [LintLevel] has occurred,
+    /// and should be reported as an error.
+    ///
+    /// The default implementation forwards it to `emit_warning`.
+    fn emit_denied_warning(&mut self, warning: X::Warning) {
+        self.emit_warning(warning)
+    }
+    /// Called by the `ToolInitEnv` drop handler.
     /// The receiver may need to propagate this call.
     ///
     /// If an implementation buffers errors emitting them here
@@ -15,7 +27,151 @@ pub trait Diagnostics<X: Tool> {
     /// outlives multiple `DiagnosticsEmitters`s by being passed
     /// through multiple drivers, and drain those buffers here.
     /// To avoid emitting the same errors multiple times.
-    fn no_more_data(&mut self);
+    ///
+    /// `env` gives access to the source text which spans refer to.
+    fn no_more_data(&mut self, env: &DiagnosticsEnv<'_, X>);
+}
+
+/// Implemented by the `Diagnostics` in this crate which write their output once
+/// the source text is available.
+///
+/// Diagnostics are buffered in the order they were emitted until `no_more_data`,
+/// which passes them to `write_diagnostics`. Errors writing the output are ignored,
+/// as there is nowhere left to report them.
+pub(crate) trait BufferedDiagnostics<X: Tool> {
+    fn pending(&mut self) -> &mut Vec<Diagnostic<X>>;
+    fn write_diagnostics(&mut self, diagnostics: Vec<Diagnostic<X>>, env: &DiagnosticsEnv<'_, X>);
+}
+
+/// Implements `Diagnostics` for `$sink<X, W>` through its [BufferedDiagnostics] impl.
+macro_rules! buffered_diagnostics {
+    ($sink:ident) => {
+        impl<X: Tool, W: std::io::Write> $crate::diagnostics::Diagnostics<X> for $sink<X, W> {
+            fn emit_error(&mut self, e: X::Error) {
+                self.pending().push(Diagnostic::Error(e));
+            }
+            fn emit_warning(&mut self, w: X::Warning) {
+                self.pending().push(Diagnostic::Warning(w));
+            }
+            fn emit_denied_warning(&mut self, w: X::Warning) {
+                self.pending().push(Diagnostic::DeniedWarning(w));
+            }
+            fn no_more_data(&mut self, env: &DiagnosticsEnv<'_, X>) {
+                let diagnostics = std::mem::take(self.pending());
+                self.write_diagnostics(diagnostics, env);
+            }
+        }
+    };
+}
+pub(crate) use buffered_diagnostics;
+
+/// The environment available to `Diagnostics` when no more data will be emitted.
+pub struct DiagnosticsEnv<'a, X: Tool> {
+    pub(crate) source_cache: &'a SourceCache,
+    pub(crate) session: &'a Session<X::SourceKind>,
+}
+
+impl<'a, X: Tool> DiagnosticsEnv<'a, X> {
+    pub fn new(source_cache: &'a SourceCache, session: &'a Session<X::SourceKind>) -> Self {
+        Self {
+            source_cache,
+            session,
+        }
+    }
+    /// The cache containing the source text which diagnostics refer to.
+    pub fn source_cache(&self) -> &'a SourceCache {
+        self.source_cache
+    }
+    /// The session of the driver run which produced the diagnostics.
+    pub fn session(&self) -> &'a Session<X::SourceKind> {
+        self.session
+    }
+}
+
+/// The severity of a diagnostic.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Either an error or a warning from a tool.
+///
+/// Useful for implementations of `Diagnostics` which buffer
+/// errors and warnings while preserving the order they were emitted in.
+pub enum Diagnostic<X: Tool> {
+    Error(X::Error),
+    Warning(X::Warning),
+    /// A warning which was promoted to an error by its [LintLevel].
+    DeniedWarning(X::Warning),
+}
+
+impl<X: Tool> Diagnostic<X> {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Diagnostic::Error(_) | Diagnostic::DeniedWarning(_) => Severity::Error,
+            Diagnostic::Warning(_) => Severity::Warning,
+        }
+    }
+    pub fn source_id(&self) -> Option<SourceId> {
+        match self {
+            Diagnostic::Error(e) => e.source_id(),
+            Diagnostic::Warning(w) | Diagnostic::DeniedWarning(w) => w.source_id(),
+        }
+    }
+    pub fn spans(&self) -> &[Span] {
+        match self {
+            Diagnostic::Error(e) => e.spans(),
+            Diagnostic::Warning(w) | Diagnostic::DeniedWarning(w) => w.spans(),
+        }
+    }
+    /// The code of the diagnostic, if it has one.
+    pub fn code(&self) -> Option<&'static str> {
+        match self {
+            Diagnostic::Error(e) => e.code(),
+            Diagnostic::Warning(w) | Diagnostic::DeniedWarning(w) => w.code(),
+        }
+    }
+    /// The lint name of a warning.
+    pub fn lint_name(&self) -> Option<&'static str> {
+        match self {
+            Diagnostic::Error(_) => None,
+            Diagnostic::Warning(w) | Diagnostic::DeniedWarning(w) => Some(w.lint_name()),
+        }
+    }
+    /// The label of the span at `idx` as given by [Spanned::format_span].
+    pub fn span_label(&self, idx: usize) -> Option<String> {
+        match self {
+            Diagnostic::Error(e) => e.format_span(idx).map(|label| label.to_string()),
+            Diagnostic::Warning(w) | Diagnostic::DeniedWarning(w) => {
+                w.format_span(idx).map(|label| label.to_string())
+            }
+        }
+    }
+}
+
+impl<X: Tool> fmt::Display for Diagnostic<X> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Diagnostic::Error(e) => e.fmt(f),
+            Diagnostic::Warning(w) | Diagnostic::DeniedWarning(w) => w.fmt(f),
+        }
+    }
 }
 
 /// Sends ownership and observes emission of diagnostics from a tool.
@@ -26,22 +182,15 @@ where
 {
     observed_warning: bool,
     observed_error: bool,
+    sent_no_more_data: bool,
+    lint_levels: LintLevels,
+    suggestions: HashMap<SourceId, Vec<Suggestion>>,
     diagnostics: &'diag mut D,
     // This is primarily used to guide inference.
     #[allow(unused)]
     tool: X,
 }
 
-impl<'diag, X: Tool, Diag> Drop for DiagnosticsEmitter<'diag, X, Diag>
-where
-    Diag: Diagnostics<X>,
-{
-    /// Calls `Diagnostics::no_more_data()`
-    fn drop(&mut self) {
-        self.diagnostics.no_more_data()
-    }
-}
-
 impl<'diag, X, D> DiagnosticsEmitter<'diag, X, D>
 where
     X: Tool,
@@ -51,15 +200,23 @@ where
         Self {
             observed_error: false,
             observed_warning: false,
+            sent_no_more_data: false,
+            lint_levels: LintLevels::default(),
+            suggestions: HashMap::new(),
             diagnostics,
             tool,
         }
     }
+    pub(crate) fn with_lint_levels(mut self, lint_levels: LintLevels) -> Self {
+        self.lint_levels = lint_levels;
+        self
+    }
     /// 1. Notes the indication of an error for later observation.
     /// 2. Sends the error off to be owned by `self.diagnostics`.
     /// 3. Returns a `ToolError::ToolFailure`
     pub fn emit_error(&mut self, e: X::Error) -> Result<(), ToolError> {
         self.observed_error = true;
+        self.collect_suggestions(e.source_id(), e.suggestions());
         self.diagnostics.emit_error(e);
         Err(ToolError::ToolFailure)
     }
@@ -67,13 +224,32 @@ where
     /// 2. Sends the error off to be owned by `self.diagnostics`.
     pub fn emit_non_fatal_error(&mut self, e: X::Error) {
         self.observed_error = true;
+        self.collect_suggestions(e.source_id(), e.suggestions());
         self.diagnostics.emit_error(e);
     }
-    /// 1. Notes the indication of the warning for later observation.
-    /// 2. Sends the warning off to be owned by `self.diagnostics`.
+    /// Handles the warning according to the [LintLevel] of its lint name.
+    ///
+    /// * `Allow`: The warning is dropped.
+    /// * `Warn`: Notes the indication of the warning for later observation,
+    ///   and sends it off to be owned by `self.diagnostics`.
+    /// * `Deny` or `Forbid`: Notes the indication of an error for later observation,
+    ///   and sends it off to `self.diagnostics` as a denied warning.
     pub fn emit_warning(&mut self, w: X::Warning) {
-        self.observed_warning = true;
-        self.diagnostics.emit_warning(w);
+        let level = self.lint_levels.level(w.lint_name());
+        if level != LintLevel::Allow {
+            self.collect_suggestions(w.source_id(), w.suggestions());
+        }
+        match level {
+            LintLevel::Allow => (),
+            LintLevel::Warn => {
+                self.observed_warning = true;
+                self.diagnostics.emit_warning(w);
+            }
+            LintLevel::Deny | LintLevel::Forbid => {
+                self.observed_error = true;
+                self.diagnostics.emit_denied_warning(w);
+            }
+        }
     }
 
     /// Returns whether any errors have been observed during it's lifetime.
@@ -84,4 +260,26 @@ where
     pub fn observed_warning(&self) -> bool {
         self.observed_warning
     }
+
+    fn collect_suggestions(&mut self, src_id: Option<SourceId>, suggestions: &[Suggestion]) {
+        if let (Some(src_id), false) = (src_id, suggestions.is_empty()) {
+            self.suggestions
+                .entry(src_id)
+                .or_default()
+                .extend_from_slice(suggestions);
+        }
+    }
+
+    /// Takes the suggestions of every diagnostic emitted so far, by `SourceId`.
+    pub(crate) fn take_suggestions(&mut self) -> HashMap<SourceId, Vec<Suggestion>> {
+        std::mem::take(&mut self.suggestions)
+    }
+
+    /// Calls `Diagnostics::no_more_data()` unless it has already been called.
+    pub(crate) fn no_more_data(&mut self, env: &DiagnosticsEnv<'_, X>) {
+        if !self.sent_no_more_data {
+            self.sent_no_more_data = true;
+            self.diagnostics.no_more_data(env)
+        }
+    }
 }
@@ -0,0 +1,232 @@
+use crate::source::{SourceCache, SourceId};
+use std::fmt::Write as _;
+
+/// The number of lines of unchanged context surrounding each hunk.
+pub const DIFF_CONTEXT_LINES: usize = 3;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Produces a line based unified diff between `old` and `new`.
+///
+/// `old_name` and `new_name` are used verbatim in the `---` and `+++` headers,
+/// so a diff intended for `patch -p1` should use names such as `a/src/grammar.y`
+/// and `b/src/grammar.y`, or `/dev/null` for a file which does not exist.
+/// Returns an empty string if the texts are equal.
+pub fn unified_diff(old_name: &str, old: &str, new_name: &str, new: &str) -> String {
+    if old == new {
+        return String::new();
+    }
+    let old_lines = old.split_inclusive('\n').collect::<Vec<_>>();
+    let new_lines = new.split_inclusive('\n').collect::<Vec<_>>();
+    let edits = diff_lines(&old_lines, &new_lines);
+    let mut out = format!("--- {old_name}\n+++ {new_name}\n");
+    for (hunk_start, hunk_end) in hunks(&edits) {
+        let hunk = &edits[hunk_start..hunk_end];
+        let old_start = hunk.iter().find_map(|edit| match edit {
+            Edit::Equal(i, _) | Edit::Delete(i) => Some(*i),
+            Edit::Insert(_) => None,
+        });
+        let new_start = hunk.iter().find_map(|edit| match edit {
+            Edit::Equal(_, j) | Edit::Insert(j) => Some(*j),
+            Edit::Delete(_) => None,
+        });
+        let old_len = hunk
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Insert(_)))
+            .count();
+        let new_len = hunk
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Delete(_)))
+            .count();
+        // An empty range is numbered by the line preceding it.
+        let range_start = |start: Option<usize>, len: usize, preceding: usize| match start {
+            Some(start) if len != 0 => start + 1,
+            _ => preceding,
+        };
+        let old_preceding = preceding_lines(&edits[..hunk_start], true);
+        let new_preceding = preceding_lines(&edits[..hunk_start], false);
+        writeln!(
+            out,
+            "@@ -{},{} +{},{} @@",
+            range_start(old_start, old_len, old_preceding),
+            old_len,
+            range_start(new_start, new_len, new_preceding),
+            new_len
+        )
+        .unwrap();
+        for edit in hunk {
+            let (prefix, line) = match *edit {
+                Edit::Equal(i, _) => (' ', old_lines[i]),
+                Edit::Delete(i) => ('-', old_lines[i]),
+                Edit::Insert(j) => ('+', new_lines[j]),
+            };
+            out.push(prefix);
+            out.push_str(line);
+            if !line.ends_with('\n') {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    out
+}
+
+/// Produces a unified diff between two sources in `source_cache`, for use with `patch -p1`.
+///
+/// The headers use the paths of the sources prefixed with `a/` and `b/`.
+/// Returns `None` if either `SourceId` is not in the cache.
+pub fn source_diff(source_cache: &SourceCache, old: SourceId, new: SourceId) -> Option<String> {
+    let old_path = source_cache.path_for_id(old)?;
+    let new_path = source_cache.path_for_id(new)?;
+    Some(unified_diff(
+        &format!("a/{}", old_path.display()),
+        source_cache.source_for_id(old)?,
+        &format!("b/{}", new_path.display()),
+        source_cache.source_for_id(new)?,
+    ))
+}
+
+/// The number of old (or new) lines within `edits`.
+fn preceding_lines(edits: &[Edit], old: bool) -> usize {
+    edits
+        .iter()
+        .filter(|edit| match edit {
+            Edit::Equal(..) => true,
+            Edit::Delete(_) => old,
+            Edit::Insert(_) => !old,
+        })
+        .count()
+}
+
+/// Groups the changes within `edits` into hunks with surrounding context.
+///
+/// Returns ranges of indices into `edits`.
+fn hunks(edits: &[Edit]) -> Vec<(usize, usize)> {
+    let mut hunks: Vec<(usize, usize)> = vec![];
+    for (idx, _) in edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Equal(..)))
+    {
+        let start = idx.saturating_sub(DIFF_CONTEXT_LINES);
+        let end = (idx + 1 + DIFF_CONTEXT_LINES).min(edits.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    hunks
+}
+
+/// Computes a shortest edit script between `a` and `b` using Myers' algorithm.
+///
+/// Uses the linear space refinement, which splits the texts at the middle snake of
+/// an optimal path and recurses on either side, so a completely rewritten source
+/// only needs memory proportional to its length.
+fn diff_lines(a: &[&str], b: &[&str]) -> Vec<Edit> {
+    let mut edits = Vec::with_capacity(a.len().max(b.len()));
+    diff_range(a, b, 0, 0, &mut edits);
+    edits
+}
+
+/// Appends the edits between `a` and `b`, which start at lines `a_start` and `b_start`.
+fn diff_range(a: &[&str], b: &[&str], a_start: usize, b_start: usize, edits: &mut Vec<Edit>) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    edits.extend((0..prefix).map(|i| Edit::Equal(a_start + i, b_start + i)));
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let (a_mid_start, b_mid_start) = (a_start + prefix, b_start + prefix);
+    if a_mid.is_empty() {
+        edits.extend((0..b_mid.len()).map(|j| Edit::Insert(b_mid_start + j)));
+    } else if b_mid.is_empty() {
+        edits.extend((0..a_mid.len()).map(|i| Edit::Delete(a_mid_start + i)));
+    } else {
+        // Both sides are non-empty and differ in their first and last lines, so the
+        // edit distance is at least 2 and each half has a strictly smaller distance.
+        let (x, y, u, v) = middle_snake(a_mid, b_mid);
+        diff_range(&a_mid[..x], &b_mid[..y], a_mid_start, b_mid_start, edits);
+        edits.extend((0..u - x).map(|i| Edit::Equal(a_mid_start + x + i, b_mid_start + y + i)));
+        diff_range(
+            &a_mid[u..],
+            &b_mid[v..],
+            a_mid_start + u,
+            b_mid_start + v,
+            edits,
+        );
+    }
+    edits.extend((0..suffix).map(|i| {
+        Edit::Equal(
+            a_start + a.len() - suffix + i,
+            b_start + b.len() - suffix + i,
+        )
+    }));
+}
+
+/// Finds the middle snake of a shortest edit script between non-empty `a` and `b`.
+///
+/// Searches forwards from the start and backwards from the end until the paths
+/// overlap, returning the snake from `(x, y)` to `(u, v)` where they met.
+fn middle_snake(a: &[&str], b: &[&str]) -> (usize, usize, usize, usize) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+    // The furthest x reached on each diagonal, forwards and backwards from the end.
+    let mut forward = vec![0isize; (2 * max + 3) as usize];
+    let mut backward = vec![0isize; (2 * max + 3) as usize];
+    let idx = |k: isize| (k + offset) as usize;
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[idx(k - 1)] < forward[idx(k + 1)]) {
+                forward[idx(k + 1)]
+            } else {
+                forward[idx(k - 1)] + 1
+            };
+            let mut y = x - k;
+            let (x0, y0) = (x, y);
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[idx(k)] = x;
+            let reverse_k = delta - k;
+            if odd && (-(d - 1)..=d - 1).contains(&reverse_k) && x + backward[idx(reverse_k)] >= n {
+                return (x0 as usize, y0 as usize, x as usize, y as usize);
+            }
+        }
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && backward[idx(k - 1)] < backward[idx(k + 1)]) {
+                backward[idx(k + 1)]
+            } else {
+                backward[idx(k - 1)] + 1
+            };
+            let mut y = x - k;
+            let (x0, y0) = (x, y);
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[idx(k)] = x;
+            let forward_k = delta - k;
+            if !odd && (-d..=d).contains(&forward_k) && x + forward[idx(forward_k)] >= n {
+                return (
+                    (n - x) as usize,
+                    (m - y) as usize,
+                    (n - x0) as usize,
+                    (m - y0) as usize,
+                );
+            }
+        }
+    }
+    unreachable!("the forward and backward paths always overlap")
+}
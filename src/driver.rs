@@ -1,12 +1,28 @@
 use crate::{
-    Args, Params, _unstable_api_,
+    _unstable_api_,
+    code::lookup_error_code,
     default_impls::{DefaultDriver, DefaultDriverArgs, DefaultDriverOptionalArgs},
-    diagnostics::{Diagnostics, DiagnosticsEmitter},
+    diagnostics::{Diagnostics, DiagnosticsEmitter, DiagnosticsEnv},
+    fix::FixError,
+    graph::DependencyKind,
+    include::{resolve_include, IncludeError},
+    incremental::{
+        clone_diagnostic, fingerprint, replay, IncrementalOutput, IncrementalState, PreviousRun,
+        RecordingDiagnostics,
+    },
+    lint::LintLevels,
+    output::write_session_diffs,
+    provider::{Overlay, SourceProvider},
     source::{Session, SourceCache, SourceId},
     tool::{Tool, ToolInit},
-    DefaultDriverEnv, NEXT_SOURCE_ID,
+    Args, DefaultDriverEnv, Params,
+};
+use dir_view::DirView;
+use std::{
+    hash::Hash,
+    io, mem,
+    path::{Path, PathBuf},
 };
-use std::{collections::HashMap, io, io::Read as _, sync::atomic::Ordering};
 
 /// A marker trait for selecting between drivers.
 pub trait DriverSelector: _unstable_api_::InternalTrait {}
@@ -33,6 +49,17 @@ where
     pub tool_args: (X::RequiredArgs, X::OptionalArgs),
 }
 
+impl<X, D> Driver<X, D>
+where
+    X: Tool,
+    D: DriverSelector + DriverTypes<X>,
+{
+    /// Returns the markdown explanation `X` registered for the error `code`.
+    pub fn explain(code: &str) -> Option<&'static str> {
+        lookup_error_code::<X>(code).map(|error_code| error_code.explanation)
+    }
+}
+
 impl<X> Driver<X, DefaultDriver>
 where
     X: Tool,
@@ -43,53 +70,293 @@ where
     /// 1. Populates a `source_cache`
     /// 2. Constructs a `Diagnostics emitter`.
     /// 3. Passes everything above to the tool's implementation of `tool_init`.
+    /// 4. Handles the sources added by the tool according to the `OutputArgs`, if any.
     pub fn driver_init<D: Diagnostics<X>>(
         self,
         driver_env: DefaultDriverEnv<'_, X, D>,
     ) -> Result<DriverOutput<X>, DriverError> {
+        self.driver_init_with(driver_env, |tool_run, source_cache, diagnostics| {
+            Ok(tool_run.run(source_cache, diagnostics))
+        })
+    }
+
+    /// `driver_init`, where `run` runs the tool once the sources are read.
+    pub(crate) fn driver_init_with<D, R>(
+        self,
+        driver_env: DefaultDriverEnv<'_, X, D>,
+        run: R,
+    ) -> Result<DriverOutput<X>, DriverError>
+    where
+        D: Diagnostics<X>,
+        R: FnOnce(ToolRun<X>, &mut SourceCache, &mut D) -> Result<DriverOutput<X>, DriverError>,
+    {
         let mut driver_options: Params<DefaultDriver> = self.driver_args.into();
-        let mut source_ids_from_driver = Vec::new();
-        let mut add_to_src_cache = |source_path, source| {
-            let source_id = SourceId(NEXT_SOURCE_ID.fetch_add(1, Ordering::SeqCst));
-            driver_env
-                .source_cache
-                .cache
-                .insert(source_id, (source_path, source));
-            source_ids_from_driver.push(source_id);
+        let optional = &mut driver_options.optional;
+        let tool_run = ToolRun {
+            tool: self.tool,
+            tool_args: self.tool_args,
+            loaded: load_sources(optional)?,
+            include_provider: include_provider(optional),
+            lint_levels: mem::take(&mut optional.lint_levels),
         };
-        if let Some((source_path, source)) = driver_options.optional.named_string.take() {
-            add_to_src_cache(source_path, source);
+        let driver_output = run(tool_run, driver_env.source_cache, driver_env.diagnostics)?;
+        if let Some(mut output_args) = optional.output.take() {
+            output_args.run(&driver_output.session, driver_env.source_cache)?;
         }
-        if let Some((source_path, dir)) = driver_options.optional.read_source {
-            let mut file = dir.open(&source_path)?;
-            let mut source = String::new();
+        Ok(driver_output)
+    }
 
-            file.read_to_string(&mut source)?;
-            add_to_src_cache(source_path, source);
-        }
-        let emitter = DiagnosticsEmitter::new(self.tool, driver_env.diagnostics);
-        let session = Session {
-            source_ids_from_driver,
-            source_ids_from_tool: vec![],
-            source_kinds: HashMap::new(),
-        };
-        let mut tool_env = ToolInitEnv {
-            source_cache: driver_env.source_cache,
-            emitter,
-            session,
+    /// Like `driver_init`, but reuses the output of the previous run in `state` if nothing changed.
+    ///
+    /// The driver fingerprints the tool arguments, lint levels, and the path and text
+    /// of every loaded source. If they match the previous run, and the sources it
+    /// included are unchanged, `tool_init` is not called. The diagnostics of the previous
+    /// run are replayed, and its output and session are returned. Otherwise the `DiskCache`
+    /// of `state`, if any, is consulted as in `driver_init_cached`.
+    pub fn driver_init_incremental<D: Diagnostics<X>>(
+        self,
+        driver_env: DefaultDriverEnv<'_, X, D>,
+        state: &mut IncrementalState<X>,
+    ) -> Result<DriverOutput<X>, DriverError>
+    where
+        X::Output: IncrementalOutput,
+        X::Error: Clone,
+        X::Warning: Clone,
+        X::SourceKind: Clone,
+        X::RequiredArgs: Hash,
+        X::OptionalArgs: Hash,
+    {
+        let mut driver_options: Params<DefaultDriver> = self.driver_args.into();
+        let optional = &mut driver_options.optional;
+        let loaded = load_sources(optional)?;
+        let fingerprint = fingerprint(&self.tool_args, &optional.lint_levels, &loaded);
+        let mut include_provider = include_provider(optional);
+        let previous = state.take_previous().filter(|previous| {
+            previous.fingerprint == fingerprint
+                && previous.is_current(driver_env.source_cache, include_provider.as_mut())
+        });
+        let (previous, reused, cache_status, cache_warning) = match previous {
+            Some(previous) => {
+                // The returned session refers to the sources as well as the previous one.
+                for src_id in previous.session.all_source_ids() {
+                    driver_env.source_cache.retain(src_id);
+                }
+                let env = DiagnosticsEnv::new(driver_env.source_cache, &previous.session);
+                let recorded = previous.diagnostics.iter().map(clone_diagnostic);
+                replay(recorded, driver_env.diagnostics, &env);
+                (previous, true, None, None)
+            }
+            None => {
+                let mut recorder = RecordingDiagnostics {
+                    inner: driver_env.diagnostics,
+                    recorded: vec![],
+                };
+                let tool_run = ToolRun {
+                    tool: self.tool,
+                    tool_args: self.tool_args,
+                    loaded,
+                    include_provider,
+                    lint_levels: mem::take(&mut optional.lint_levels),
+                };
+                #[cfg(feature = "cache")]
+                let driver_output = match &state.disk_cache {
+                    Some(cache) => cache.run(tool_run, driver_env.source_cache, &mut recorder)?,
+                    None => tool_run.run(driver_env.source_cache, &mut recorder),
+                };
+                #[cfg(not(feature = "cache"))]
+                let driver_output = tool_run.run(driver_env.source_cache, &mut recorder);
+                let previous = PreviousRun {
+                    fingerprint,
+                    output: driver_output.output,
+                    session: driver_output.session,
+                    diagnostics: recorder.recorded,
+                };
+                let cache_status = driver_output.cache_status;
+                (previous, false, cache_status, driver_output.cache_warning)
+            }
         };
-        let output = X::Output::tool_init(self.tool_args.into(), &mut tool_env);
+        let output = previous.output.clone();
+        let session = previous.session.clone();
+        state.set_previous(previous, reused);
+        if let Some(mut output_args) = optional.output.take() {
+            output_args.run(&session, driver_env.source_cache)?;
+        }
         Ok(DriverOutput {
             output,
-            session: tool_env.session,
+            session,
+            cache_status,
+            cache_warning,
         })
     }
 }
+
+/// A source read by the driver, which has not yet been added to the `SourceCache`.
+pub(crate) struct LoadedSource {
+    pub(crate) path: PathBuf,
+    pub(crate) src: String,
+    /// See [SourceProvider::file_path].
+    pub(crate) file_path: Option<PathBuf>,
+}
+
+/// Reads every source given in `optional`, in the order they are loaded into the `SourceCache`.
+pub(crate) fn load_sources(
+    optional: &mut DefaultDriverOptionalArgs,
+) -> Result<Vec<LoadedSource>, DriverError> {
+    let mut loaded = vec![];
+    if let Some((path, src)) = optional.named_string.take() {
+        loaded.push(LoadedSource {
+            path,
+            src,
+            file_path: None,
+        });
+    }
+    let overlay = &optional.overlay;
+    let mut load = |provider: &mut dyn SourceProvider, path: PathBuf| {
+        let src = match overlay.get(&path) {
+            Some(src) => src.to_string(),
+            None => provider.read_source(&path)?,
+        };
+        let file_path = provider.file_path(&path);
+        loaded.push(LoadedSource {
+            path,
+            src,
+            file_path,
+        });
+        Ok::<_, io::Error>(())
+    };
+    if let Some((path, dir)) = &mut optional.read_source {
+        load(dir, path.clone())?;
+    }
+    if let Some(mut stdin) = optional.read_stdin.take() {
+        let path = stdin.name.clone();
+        load(&mut stdin, path)?;
+    }
+    for mut provided in mem::take(&mut optional.provided_sources) {
+        for path in provided.paths {
+            load(provided.provider.as_mut(), path)?;
+        }
+    }
+    for inputs in &mut optional.read_sources {
+        for path in inputs.resolve()? {
+            load(&mut inputs.dir, path)?;
+        }
+    }
+    Ok(loaded)
+}
+
+/// Adds `loaded` to the `SourceCache`, returning a session recording them.
+pub(crate) fn insert_sources<SourceKind>(
+    source_cache: &mut SourceCache,
+    loaded: Vec<LoadedSource>,
+) -> Session<SourceKind> {
+    let mut session = Session::default();
+    for source in loaded {
+        let src_id = source_cache.load(source.path, source.src);
+        session.source_ids_from_driver.push(src_id);
+        session.add_file_dependency(source.file_path);
+    }
+    session
+}
+
+/// The include provider given in `optional`, with the overlay applied.
+///
+/// Defaults to the dir of `read_source`, or else of the first of `read_sources`,
+/// so that the sources the driver read may include the files next to them.
+pub(crate) fn include_provider(
+    optional: &mut DefaultDriverOptionalArgs,
+) -> Option<Box<dyn SourceProvider>> {
+    let read_source_dir = optional.read_source.take().map(|(_, dir)| dir);
+    let read_sources_dir = || {
+        let inputs = mem::take(&mut optional.read_sources).into_iter().next()?;
+        Some(inputs.dir)
+    };
+    let provider = match optional.include_provider.take() {
+        Some(provider) => provider,
+        None => Box::new(read_source_dir.or_else(read_sources_dir)?),
+    };
+    Some(Box::new(Overlay::new(
+        mem::take(&mut optional.overlay),
+        provider,
+    )))
+}
+
+/// The inputs of a run of the tool, once the driver has read its sources.
+pub(crate) struct ToolRun<X: Tool> {
+    pub(crate) tool: X,
+    pub(crate) tool_args: (X::RequiredArgs, X::OptionalArgs),
+    pub(crate) loaded: Vec<LoadedSource>,
+    pub(crate) include_provider: Option<Box<dyn SourceProvider>>,
+    pub(crate) lint_levels: LintLevels,
+}
+
+impl<X: Tool> ToolRun<X> {
+    /// Adds the loaded sources to `source_cache` and runs the tool.
+    pub(crate) fn run<D: Diagnostics<X>>(
+        self,
+        source_cache: &mut SourceCache,
+        diagnostics: &mut D,
+    ) -> DriverOutput<X> {
+        let session = insert_sources(source_cache, self.loaded);
+        let (output, session) = run_tool(
+            self.tool,
+            Params::from(self.tool_args),
+            self.lint_levels,
+            self.include_provider,
+            session,
+            source_cache,
+            diagnostics,
+        );
+        DriverOutput {
+            output,
+            session,
+            cache_status: None,
+            cache_warning: None,
+        }
+    }
+}
+
+/// Constructs the emitter and `ToolInitEnv`, and runs `tool_init`.
+pub(crate) fn run_tool<X: Tool, D: Diagnostics<X>>(
+    tool: X,
+    params: Params<X>,
+    lint_levels: LintLevels,
+    include_provider: Option<Box<dyn SourceProvider>>,
+    session: Session<X::SourceKind>,
+    source_cache: &mut SourceCache,
+    diagnostics: &mut D,
+) -> (X::Output, Session<X::SourceKind>) {
+    let emitter = DiagnosticsEmitter::new(tool, diagnostics).with_lint_levels(lint_levels);
+    let mut tool_env = ToolInitEnv {
+        source_cache,
+        emitter,
+        session,
+        include_provider,
+    };
+    let output = X::Output::tool_init(params, &mut tool_env);
+    (output, tool_env.finish())
+}
+
 /// Errors occurred by the driver.
 #[derive(thiserror::Error, Debug)]
 pub enum DriverError {
     #[error("Io error {0} ")]
     Io(#[from] io::Error),
+    #[error("OutputArgs filter does not match the tool's SourceKind")]
+    SourceKindMismatch,
+    /// Sources which differ from the file on disk in `OutputMode::Check`.
+    #[error("Generated sources are out of date: {}", display_paths(.0))]
+    StaleSources(Vec<PathBuf>),
+    /// Writing a source in `OutputMode::Write` failed.
+    #[error("Failed to write {}: {source}", .path.display())]
+    Write { path: PathBuf, source: io::Error },
+}
+
+fn display_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 pub trait DriverTypes<X: Tool>: Args {
@@ -111,6 +378,93 @@ where
     pub emitter: DiagnosticsEmitter<'a, X, D>,
     pub source_cache: &'a mut SourceCache,
     pub session: Session<X::SourceKind>,
+    /// The provider for `include`, which has the driver's overlay applied.
+    pub include_provider: Option<Box<dyn SourceProvider>>,
+}
+
+impl<'a, X, D> ToolInitEnv<'a, X, D>
+where
+    X: Tool,
+    D: Diagnostics<X>,
+{
+    /// Loads `path`, relative to the path of the `including` source, through the include provider.
+    ///
+    /// The new `SourceId` is recorded in the session as included from `including`.
+    /// Including a path which is `including` or one of the sources which included it
+    /// is an `IncludeError::Cycle`, including any other path which has already been
+    /// loaded is an `IncludeError::AlreadyIncluded`.
+    pub fn include(
+        &mut self,
+        including: SourceId,
+        path: impl AsRef<Path>,
+    ) -> Result<SourceId, IncludeError> {
+        let including_path = self
+            .source_cache
+            .path_for_id(including)
+            .ok_or(IncludeError::UnknownSource(including))?;
+        let path = resolve_include(including_path, path.as_ref());
+        let mut chain = vec![path.clone()];
+        let mut ancestor = Some(including);
+        while let Some(src_id) = ancestor {
+            let ancestor_path = self.source_cache.path_for_id(src_id);
+            chain.push(ancestor_path.map(Path::to_path_buf).unwrap_or_default());
+            if ancestor_path == Some(path.as_path()) {
+                chain.reverse();
+                return Err(IncludeError::Cycle(chain));
+            }
+            ancestor = self.session.included_from(src_id);
+        }
+        let loaded = self
+            .session
+            .loaded_source_ids()
+            .iter()
+            .chain(self.session.included_source_ids());
+        for src_id in loaded {
+            if self.source_cache.path_for_id(*src_id) == Some(path.as_path()) {
+                return Err(IncludeError::AlreadyIncluded {
+                    path,
+                    src_id: *src_id,
+                });
+            }
+        }
+        let provider = self
+            .include_provider
+            .as_mut()
+            .ok_or(IncludeError::NoProvider)?;
+        let source = provider
+            .read_source(&path)
+            .map_err(|source| IncludeError::Io {
+                path: path.clone(),
+                source,
+            })?;
+        self.session.add_file_dependency(provider.file_path(&path));
+        let src_id = self.source_cache.load(path, source);
+        self.session.source_ids_from_includes.push(src_id);
+        self.session
+            .add_dependency(including, src_id, DependencyKind::Include);
+        Ok(src_id)
+    }
+
+    /// Signals `no_more_data` while the session is still available to it,
+    /// then returns the session.
+    pub(crate) fn finish(mut self) -> Session<X::SourceKind> {
+        self.session.suggestions = self.emitter.take_suggestions();
+        let env = DiagnosticsEnv::new(self.source_cache, &self.session);
+        self.emitter.no_more_data(&env);
+        std::mem::take(&mut self.session)
+    }
+}
+
+impl<'a, X, D> Drop for ToolInitEnv<'a, X, D>
+where
+    X: Tool,
+    D: Diagnostics<X>,
+{
+    /// Calls `Diagnostics::no_more_data()`
+    fn drop(&mut self) {
+        let env = DiagnosticsEnv::new(self.source_cache, &self.session);
+        self.emitter.no_more_data(&env)
+    }
 }
 
 /// The return value of `driver_init`.
@@ -119,4 +473,45 @@ where
 pub struct DriverOutput<X: Tool> {
     pub session: Session<X::SourceKind>,
     pub output: X::Output,
+    /// Whether the run was restored from the `DiskCache`, or `None` if there was none
+    /// or the run was reused by `driver_init_incremental`.
+    pub cache_status: Option<CacheStatus>,
+    /// An error writing the entry for the run to the `DiskCache`, which did not fail the run.
+    pub cache_warning: Option<DriverError>,
+}
+
+/// Whether a `DiskCache` had an entry for a driver run.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// The output and diagnostics were read from the cache, `tool_init` was not called.
+    Hit,
+    /// `tool_init` was called, and its output and diagnostics were written to the cache
+    /// unless there is a `cache_warning`.
+    Miss,
+}
+
+impl<X: Tool> DriverOutput<X> {
+    /// Applies the machine applicable fixes suggested for `src_id` during the driver run.
+    ///
+    /// See [Session::apply_fixes].
+    pub fn apply_fixes(
+        &mut self,
+        source_cache: &mut SourceCache,
+        src_id: SourceId,
+    ) -> Result<Option<SourceId>, FixError> {
+        self.session.apply_fixes(source_cache, src_id)
+    }
+
+    /// Writes a unified diff for every fixed source, and every added source selected by `filter`.
+    ///
+    /// See [write_session_diffs].
+    pub fn write_diffs(
+        &self,
+        source_cache: &SourceCache,
+        dir: &DirView,
+        filter: impl Fn(&X::SourceKind) -> bool,
+        out: &mut dyn io::Write,
+    ) -> Result<usize, DriverError> {
+        write_session_diffs(&self.session, source_cache, dir, filter, out)
+    }
 }
@@ -0,0 +1,407 @@
+use crate::{
+    fix::{Applicability, Suggestion},
+    source::SourceId,
+    Span,
+};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, hash::Hasher, path::PathBuf};
+
+/// A `Hasher` computing a SHA-256 digest of the values hashed.
+///
+/// Unlike `DefaultHasher` its hashes are the same across processes, builds and
+/// platforms, integers are hashed as little endian and `usize` as 64 bits,
+/// so they may be persisted.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StableHasher(Sha256);
+
+impl StableHasher {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// The digest of the values hashed so far.
+    pub(crate) fn digest(&self) -> [u8; 32] {
+        self.0.clone().finalize().into()
+    }
+}
+
+impl Hasher for StableHasher {
+    /// The first 8 bytes of the digest.
+    fn finish(&self) -> u64 {
+        let digest = self.digest();
+        u64::from_le_bytes(digest[..8].try_into().unwrap())
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes())
+    }
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes())
+    }
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes())
+    }
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes())
+    }
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64)
+    }
+}
+
+/// Errors decoding a value written by [Encode].
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    #[error("Unexpected end of encoded data")]
+    UnexpectedEnd,
+    /// An enum discriminant which no variant was encoded with.
+    #[error("Invalid tag {0}")]
+    InvalidTag(u8),
+    /// A `SourceId` which does not belong to the sources being decoded.
+    #[error("Unknown source id {0}")]
+    UnknownSourceId(u64),
+    #[error("Invalid encoded data: {0}")]
+    Invalid(&'static str),
+}
+
+/// A buffer values are encoded into.
+///
+/// Integers are written as little endian, and `usize` as 64 bits.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+    pub fn write_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    /// Writes the length of `bytes` followed by `bytes`.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u64(bytes.len() as u64);
+        self.bytes.extend_from_slice(bytes);
+    }
+}
+
+/// Reads values written by an [Encoder].
+///
+/// Decoded `SourceId`s are mapped to the ids the sources they referred to
+/// were given when they were loaded again.
+#[derive(Debug)]
+pub struct Decoder<'a> {
+    input: &'a [u8],
+    source_ids: &'a HashMap<u64, SourceId>,
+}
+
+impl<'a> Decoder<'a> {
+    pub(crate) fn new(input: &'a [u8], source_ids: &'a HashMap<u64, SourceId>) -> Self {
+        Self { input, source_ids }
+    }
+    /// Whether all the input has been read.
+    pub fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.input.len() < len {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(taken)
+    }
+    pub fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+    pub fn read_u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    /// Reads bytes written by [Encoder::write_bytes].
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let len = self.read_u64()?;
+        let len = usize::try_from(len).map_err(|_| DecodeError::UnexpectedEnd)?;
+        self.take(len)
+    }
+}
+
+/// Types which may be written to a [DiskCache](crate::DiskCache).
+pub trait Encode {
+    fn encode(&self, encoder: &mut Encoder);
+}
+
+/// Types which may be read from a [DiskCache](crate::DiskCache).
+pub trait Decode: Sized {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError>;
+}
+
+macro_rules! int_impls {
+    ($($ty:ty),*) => {$(
+        impl Encode for $ty {
+            fn encode(&self, encoder: &mut Encoder) {
+                encoder.write_u64(*self as u64);
+            }
+        }
+        impl Decode for $ty {
+            fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+                <$ty>::try_from(decoder.read_u64()?)
+                    .map_err(|_| DecodeError::Invalid(concat!("out of range ", stringify!($ty))))
+            }
+        }
+    )*};
+}
+
+int_impls!(u16, u32, u64, usize);
+
+impl Encode for u8 {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.write_u8(*self);
+    }
+}
+
+impl Decode for u8 {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        decoder.read_u8()
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.write_u8(u8::from(*self));
+    }
+}
+
+impl Decode for bool {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        match decoder.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl Encode for () {
+    fn encode(&self, _encoder: &mut Encoder) {}
+}
+
+impl Decode for () {
+    fn decode(_decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        Ok(())
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.write_bytes(self.as_bytes());
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.as_str().encode(encoder);
+    }
+}
+
+impl Decode for String {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let bytes = decoder.read_bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::Invalid("invalid utf-8"))
+    }
+}
+
+/// Paths are encoded lossily, as utf-8.
+impl Encode for PathBuf {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.to_string_lossy().encode(encoder);
+    }
+}
+
+impl Decode for PathBuf {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        String::decode(decoder).map(PathBuf::from)
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, encoder: &mut Encoder) {
+        (**self).encode(encoder);
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for Box<T> {
+    fn encode(&self, encoder: &mut Encoder) {
+        (**self).encode(encoder);
+    }
+}
+
+impl<T: Decode> Decode for Box<T> {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        T::decode(decoder).map(Box::new)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            None => encoder.write_u8(0),
+            Some(value) => {
+                encoder.write_u8(1);
+                value.encode(encoder);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        match decoder.read_u8()? {
+            0 => Ok(None),
+            1 => T::decode(decoder).map(Some),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.write_u64(self.len() as u64);
+        for value in self {
+            value.encode(encoder);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.as_slice().encode(encoder);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let len = decoder.read_u64()?;
+        // Not preallocated, as `len` is only checked by decoding each element.
+        let mut values = vec![];
+        for _ in 0..len {
+            values.push(T::decode(decoder)?);
+        }
+        Ok(values)
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.0.encode(encoder);
+        self.1.encode(encoder);
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        Ok((A::decode(decoder)?, B::decode(decoder)?))
+    }
+}
+
+impl<A: Encode, B: Encode, C: Encode> Encode for (A, B, C) {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.0.encode(encoder);
+        self.1.encode(encoder);
+        self.2.encode(encoder);
+    }
+}
+
+impl<A: Decode, B: Decode, C: Decode> Decode for (A, B, C) {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        Ok((
+            A::decode(decoder)?,
+            B::decode(decoder)?,
+            C::decode(decoder)?,
+        ))
+    }
+}
+
+impl Encode for SourceId {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.write_u64(self.raw());
+    }
+}
+
+impl Decode for SourceId {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let src_id = decoder.read_u64()?;
+        decoder
+            .source_ids
+            .get(&src_id)
+            .copied()
+            .ok_or(DecodeError::UnknownSourceId(src_id))
+    }
+}
+
+impl Encode for Span {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.start().encode(encoder);
+        self.end().encode(encoder);
+    }
+}
+
+impl Decode for Span {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        let start = usize::decode(decoder)?;
+        let end = usize::decode(decoder)?;
+        if end < start {
+            return Err(DecodeError::Invalid("span starts after it ends"));
+        }
+        Ok(Span::new(start, end))
+    }
+}
+
+impl Encode for Applicability {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.write_u8(match self {
+            Applicability::MachineApplicable => 0,
+            Applicability::MaybeIncorrect => 1,
+            Applicability::HasPlaceholders => 2,
+            Applicability::Unspecified => 3,
+        });
+    }
+}
+
+impl Decode for Applicability {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        match decoder.read_u8()? {
+            0 => Ok(Applicability::MachineApplicable),
+            1 => Ok(Applicability::MaybeIncorrect),
+            2 => Ok(Applicability::HasPlaceholders),
+            3 => Ok(Applicability::Unspecified),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl Encode for Suggestion {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.span.encode(encoder);
+        self.replacement.encode(encoder);
+        self.applicability.encode(encoder);
+    }
+}
+
+impl Decode for Suggestion {
+    fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+        Ok(Suggestion {
+            span: Span::decode(decoder)?,
+            replacement: String::decode(decoder)?,
+            applicability: Applicability::decode(decoder)?,
+        })
+    }
+}
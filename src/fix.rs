@@ -0,0 +1,89 @@
+use crate::{source::SourceId, Span};
+
+/// How confident a tool is that a [Suggestion] is correct.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Applicability {
+    /// The suggestion is definitely what the user intended, and may be applied automatically.
+    MachineApplicable,
+    /// The suggestion may be what the user intended, but it is uncertain.
+    MaybeIncorrect,
+    /// The suggestion contains placeholders which must be filled in by the user.
+    HasPlaceholders,
+    /// The applicability of the suggestion is unknown.
+    Unspecified,
+}
+
+/// A suggested replacement of the text covered by `span` with `replacement`.
+///
+/// The span refers to the source of the diagnostic carrying the suggestion.
+/// An empty span inserts `replacement`, an empty `replacement` deletes the span.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Suggestion {
+    pub span: Span,
+    pub replacement: String,
+    pub applicability: Applicability,
+}
+
+/// Errors applying suggestions to a source.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum FixError {
+    #[error("No source text for {0:?}")]
+    UnknownSource(SourceId),
+    #[error("Suggestion span {0:?} is outside of the source or not on a char boundary")]
+    InvalidSpan(Span),
+    /// Pairs of suggestions whose spans overlap, no fixes were applied.
+    #[error("Overlapping suggestions {0:?}")]
+    Conflicts(Vec<(Span, Span)>),
+}
+
+/// Applies every suggestion in `suggestions` to `src` regardless of its applicability.
+///
+/// Identical suggestions are applied once. Suggestions whose spans overlap, or which
+/// insert at the same position, are conflicts, and are all reported without modifying
+/// the source. Spans which merely touch do not conflict.
+pub fn apply_suggestions<'a>(
+    src: &str,
+    suggestions: impl IntoIterator<Item = &'a Suggestion>,
+) -> Result<String, FixError> {
+    let mut suggestions = suggestions.into_iter().collect::<Vec<_>>();
+    suggestions.sort_by_key(|s| (s.span.start(), s.span.end()));
+    suggestions.dedup_by(|a, b| a.span == b.span && a.replacement == b.replacement);
+    for suggestion in &suggestions {
+        let span = suggestion.span;
+        if span.end() > src.len()
+            || !src.is_char_boundary(span.start())
+            || !src.is_char_boundary(span.end())
+        {
+            return Err(FixError::InvalidSpan(span));
+        }
+    }
+    let mut conflicts = vec![];
+    // The span reaching furthest into the source so far, and the previous span.
+    let mut furthest: Option<Span> = None;
+    let mut last: Option<Span> = None;
+    for span in suggestions.iter().map(|s| s.span) {
+        if let Some(prev) = furthest.filter(|prev| span.start() < prev.end()) {
+            conflicts.push((prev, span));
+        } else if let Some(prev) =
+            last.filter(|prev| span.start() == prev.start() && (span.is_empty() || prev.is_empty()))
+        {
+            conflicts.push((prev, span));
+        }
+        if furthest.is_none_or(|prev| span.end() > prev.end()) {
+            furthest = Some(span);
+        }
+        last = Some(span);
+    }
+    if !conflicts.is_empty() {
+        return Err(FixError::Conflicts(conflicts));
+    }
+    let mut fixed = String::with_capacity(src.len());
+    let mut pos = 0;
+    for suggestion in suggestions {
+        fixed.push_str(&src[pos..suggestion.span.start()]);
+        fixed.push_str(&suggestion.replacement);
+        pos = suggestion.span.end();
+    }
+    fixed.push_str(&src[pos..]);
+    Ok(fixed)
+}
@@ -0,0 +1,142 @@
+use crate::source::{Session, SourceId};
+use std::collections::HashSet;
+
+/// How the dependent source of a [Dependency] relates to its dependency.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DependencyKind {
+    /// The dependent source included the dependency through `ToolInitEnv::include`.
+    Include,
+    /// The dependent source was generated by the tool from the dependency.
+    Generated,
+    /// The dependent source was produced by applying fixes to the dependency.
+    Fixed,
+}
+
+/// An edge of the dependency graph recorded in a `Session`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Dependency {
+    pub dependent: SourceId,
+    pub dependency: SourceId,
+    pub kind: DependencyKind,
+}
+
+impl<SourceKind> Session<SourceKind> {
+    /// Records that the tool generated `generated` from the source `from`.
+    pub fn add_generated_from(&mut self, generated: SourceId, from: SourceId) {
+        self.add_dependency(generated, from, DependencyKind::Generated);
+    }
+
+    pub(crate) fn add_dependency(
+        &mut self,
+        dependent: SourceId,
+        dependency: SourceId,
+        kind: DependencyKind,
+    ) {
+        let edge = Dependency {
+            dependent,
+            dependency,
+            kind,
+        };
+        if !self.dependencies.contains(&edge) {
+            self.dependencies.push(edge);
+        }
+    }
+
+    /// Every edge of the dependency graph, in the order they were recorded.
+    pub fn dependency_edges(&self) -> &[Dependency] {
+        &self.dependencies
+    }
+
+    /// The edges from `src_id` to the sources it directly depends on.
+    pub fn dependencies(&self, src_id: SourceId) -> impl Iterator<Item = &Dependency> {
+        self.dependencies
+            .iter()
+            .filter(move |edge| edge.dependent == src_id)
+    }
+
+    /// The edges to `src_id` from the sources which directly depend on it.
+    pub fn dependents(&self, src_id: SourceId) -> impl Iterator<Item = &Dependency> {
+        self.dependencies
+            .iter()
+            .filter(move |edge| edge.dependency == src_id)
+    }
+
+    /// Every source `src_id` depends on directly or indirectly, nearest first.
+    pub fn transitive_dependencies(&self, src_id: SourceId) -> Vec<SourceId> {
+        self.reachable(src_id, |current, edge| {
+            (edge.dependent == current).then_some(edge.dependency)
+        })
+    }
+
+    /// Every source which depends on `src_id` directly or indirectly, nearest first.
+    pub fn transitive_dependents(&self, src_id: SourceId) -> Vec<SourceId> {
+        self.reachable(src_id, |current, edge| {
+            (edge.dependency == current).then_some(edge.dependent)
+        })
+    }
+
+    /// A breadth first search from `start`, where `step(current, edge)` gives
+    /// the next source if `edge` leads away from `current`.
+    fn reachable(
+        &self,
+        start: SourceId,
+        step: impl Fn(SourceId, &Dependency) -> Option<SourceId>,
+    ) -> Vec<SourceId> {
+        let mut seen = HashSet::from([start]);
+        let mut found = vec![];
+        let mut idx = 0;
+        let mut current = Some(start);
+        while let Some(src_id) = current {
+            for next in self
+                .dependencies
+                .iter()
+                .filter_map(|edge| step(src_id, edge))
+            {
+                if seen.insert(next) {
+                    found.push(next);
+                }
+            }
+            current = found.get(idx).copied();
+            idx += 1;
+        }
+        found
+    }
+
+    /// Every source in the session ordered so that each source follows its dependencies.
+    ///
+    /// Sources are otherwise ordered as they were loaded, included, added, then fixed.
+    /// Returns `None` if the dependencies contain a cycle.
+    pub fn topological_order(&self) -> Option<Vec<SourceId>> {
+        let mut sources = vec![];
+        let edge_sources = self
+            .dependencies
+            .iter()
+            .flat_map(|edge| [edge.dependent, edge.dependency]);
+        for src_id in self
+            .source_ids_from_driver
+            .iter()
+            .chain(&self.source_ids_from_includes)
+            .chain(&self.source_ids_from_tool)
+            .chain(&self.source_ids_from_fixes)
+            .copied()
+            .chain(edge_sources)
+        {
+            if !sources.contains(&src_id) {
+                sources.push(src_id);
+            }
+        }
+        let mut ordered = Vec::with_capacity(sources.len());
+        let mut placed = HashSet::new();
+        while ordered.len() < sources.len() {
+            let next = sources.iter().copied().find(|src_id| {
+                !placed.contains(src_id)
+                    && self
+                        .dependencies(*src_id)
+                        .all(|edge| placed.contains(&edge.dependency))
+            })?;
+            placed.insert(next);
+            ordered.push(next);
+        }
+        Some(ordered)
+    }
+}
@@ -0,0 +1,53 @@
+use crate::source::SourceId;
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+/// Errors from [ToolInitEnv::include](crate::ToolInitEnv::include).
+#[derive(thiserror::Error, Debug)]
+pub enum IncludeError {
+    #[error("No include provider was given to the driver")]
+    NoProvider,
+    #[error("No source for {0:?}")]
+    UnknownSource(SourceId),
+    #[error("Failed to include {}: {source}", .path.display())]
+    Io { path: PathBuf, source: io::Error },
+    /// The chain of paths from the first inclusion of the path to its inclusion again.
+    #[error("Include cycle: {}", display_chain(.0))]
+    Cycle(Vec<PathBuf>),
+    /// The path has already been loaded as `src_id`, which may be reused.
+    #[error("{} is already included", .path.display())]
+    AlreadyIncluded { path: PathBuf, src_id: SourceId },
+}
+
+fn display_chain(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+/// Resolves `path` relative to the directory of `including`, removing `.` and `..`.
+///
+/// A `..` which would leave the root is retained, for the provider to reject.
+pub(crate) fn resolve_include(including: &Path, path: &Path) -> PathBuf {
+    let joined = including.parent().unwrap_or(Path::new("")).join(path);
+    let mut resolved = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir
+                if matches!(
+                    resolved.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                resolved.pop();
+            }
+            component => resolved.push(component),
+        }
+    }
+    resolved
+}
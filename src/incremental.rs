@@ -0,0 +1,176 @@
+#[cfg(feature = "cache")]
+use crate::cache::DiskCache;
+use crate::{
+    diagnostics::{Diagnostic, Diagnostics, DiagnosticsEnv},
+    driver::LoadedSource,
+    lint::LintLevels,
+    provider::SourceProvider,
+    source::{Session, SourceCache},
+    tool::Tool,
+};
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Implemented by tool outputs which may be reused by `Driver::driver_init_incremental`.
+///
+/// An output should only opt in if `tool_init` depends on nothing but its
+/// `Params` and the sources it reads through the `ToolInitEnv`.
+pub trait IncrementalOutput: Clone {}
+
+/// The state kept between runs of `Driver::driver_init_incremental`.
+///
+/// The same `SourceCache` must be given to each run, the previous run is only
+/// reused while every `SourceId` of its session is still in the cache.
+pub struct IncrementalState<X: Tool> {
+    previous: Option<PreviousRun<X>>,
+    reused: bool,
+    /// Consulted when the previous run can't be reused.
+    #[cfg(feature = "cache")]
+    pub(crate) disk_cache: Option<DiskCache<X>>,
+}
+
+pub(crate) struct PreviousRun<X: Tool> {
+    pub(crate) fingerprint: u64,
+    pub(crate) output: X::Output,
+    pub(crate) session: Session<X::SourceKind>,
+    /// The diagnostics which reached `Diagnostics`, after applying lint levels.
+    pub(crate) diagnostics: Vec<Diagnostic<X>>,
+}
+
+impl<X: Tool> Default for IncrementalState<X> {
+    fn default() -> Self {
+        Self {
+            previous: None,
+            reused: false,
+            #[cfg(feature = "cache")]
+            disk_cache: None,
+        }
+    }
+}
+
+impl<X: Tool> IncrementalState<X> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the most recent run reused the output of the run before it.
+    pub fn was_reused(&self) -> bool {
+        self.reused
+    }
+
+    /// Forgets the previous run, so that the next run calls `tool_init`.
+    pub fn clear(&mut self) {
+        self.previous = None;
+    }
+
+    pub(crate) fn take_previous(&mut self) -> Option<PreviousRun<X>> {
+        self.previous.take()
+    }
+
+    pub(crate) fn set_previous(&mut self, previous: PreviousRun<X>, reused: bool) {
+        self.previous = Some(previous);
+        self.reused = reused;
+    }
+}
+
+impl<X: Tool> PreviousRun<X> {
+    /// Whether the sources of the previous session are unchanged.
+    ///
+    /// The loaded sources are covered by the fingerprint, included sources
+    /// are read again through `include_provider` and compared.
+    pub(crate) fn is_current(
+        &self,
+        source_cache: &SourceCache,
+        include_provider: Option<&mut Box<dyn SourceProvider>>,
+    ) -> bool {
+        let session = &self.session;
+        let all_cached = session
+            .all_source_ids()
+            .all(|src_id| source_cache.source_for_id(src_id).is_some());
+        if !all_cached {
+            return false;
+        }
+        let included = session.included_source_ids();
+        if included.is_empty() {
+            return true;
+        }
+        let Some(provider) = include_provider else {
+            return false;
+        };
+        included.iter().all(|src_id| {
+            let path = source_cache.path_for_id(*src_id).unwrap();
+            provider.read_source(path).ok().as_deref() == source_cache.source_for_id(*src_id)
+        })
+    }
+}
+
+/// Hashes everything which determines the result of `tool_init`.
+pub(crate) fn fingerprint(
+    tool_args: &impl Hash,
+    lint_levels: &LintLevels,
+    loaded: &[LoadedSource],
+) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    tool_args.hash(&mut hasher);
+    lint_levels.hash(&mut hasher);
+    for source in loaded {
+        source.path.hash(&mut hasher);
+        source.src.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+pub(crate) fn clone_diagnostic<X: Tool>(diagnostic: &Diagnostic<X>) -> Diagnostic<X>
+where
+    X::Error: Clone,
+    X::Warning: Clone,
+{
+    match diagnostic {
+        Diagnostic::Error(e) => Diagnostic::Error(e.clone()),
+        Diagnostic::Warning(w) => Diagnostic::Warning(w.clone()),
+        Diagnostic::DeniedWarning(w) => Diagnostic::DeniedWarning(w.clone()),
+    }
+}
+
+/// Sends recorded diagnostics to `diagnostics`, followed by `no_more_data`.
+pub(crate) fn replay<X: Tool, D: Diagnostics<X>>(
+    recorded: impl IntoIterator<Item = Diagnostic<X>>,
+    diagnostics: &mut D,
+    env: &DiagnosticsEnv<'_, X>,
+) {
+    for diagnostic in recorded {
+        match diagnostic {
+            Diagnostic::Error(e) => diagnostics.emit_error(e),
+            Diagnostic::Warning(w) => diagnostics.emit_warning(w),
+            Diagnostic::DeniedWarning(w) => diagnostics.emit_denied_warning(w),
+        }
+    }
+    diagnostics.no_more_data(env);
+}
+
+/// Forwards diagnostics to another `Diagnostics`, keeping a copy of each.
+pub(crate) struct RecordingDiagnostics<'a, X: Tool, D> {
+    pub(crate) inner: &'a mut D,
+    pub(crate) recorded: Vec<Diagnostic<X>>,
+}
+
+impl<X: Tool, D: Diagnostics<X>> Diagnostics<X> for RecordingDiagnostics<'_, X, D>
+where
+    X::Error: Clone,
+    X::Warning: Clone,
+{
+    fn emit_error(&mut self, e: X::Error) {
+        self.recorded.push(Diagnostic::Error(e.clone()));
+        self.inner.emit_error(e);
+    }
+    fn emit_warning(&mut self, w: X::Warning) {
+        self.recorded.push(Diagnostic::Warning(w.clone()));
+        self.inner.emit_warning(w);
+    }
+    fn emit_denied_warning(&mut self, w: X::Warning) {
+        self.recorded.push(Diagnostic::DeniedWarning(w.clone()));
+        self.inner.emit_denied_warning(w);
+    }
+    fn no_more_data(&mut self, env: &DiagnosticsEnv<'_, X>) {
+        self.inner.no_more_data(env);
+    }
+}
@@ -0,0 +1,345 @@
+use dir_view::DirView;
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+/// A list of input files and glob patterns, resolved within a `DirView`.
+///
+/// Patterns are `/` separated paths relative to `dir`, whose components may contain
+/// `*`, `?`, and `[...]` character classes, and `**` matches any number of directories.
+/// Wildcards do not match a leading `.`, and symbolic links to directories are not followed.
+///
+/// Files matched by a pattern are skipped if they are excluded by the ignore file,
+/// which uses a subset of the `.gitignore` syntax. Paths without wildcards are
+/// always read, and it is an error if they do not exist.
+pub struct InputSources {
+    pub dir: DirView,
+    pub patterns: Vec<String>,
+    /// Path of an ignore file within `dir`, it is not an error if it does not exist.
+    pub ignore_file: Option<PathBuf>,
+}
+
+impl InputSources {
+    pub fn new(dir: DirView) -> Self {
+        Self {
+            dir,
+            patterns: vec![],
+            ignore_file: None,
+        }
+    }
+
+    /// Adds a path or glob pattern.
+    pub fn with(mut self, pattern: impl Into<String>) -> Self {
+        self.patterns.push(pattern.into());
+        self
+    }
+
+    /// Skips files excluded by the ignore file at `path`.
+    pub fn ignore_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ignore_file = Some(path.into());
+        self
+    }
+
+    /// The paths matched by every pattern, sorted and without duplicates.
+    pub fn resolve(&self) -> io::Result<Vec<PathBuf>> {
+        let ignore = match &self.ignore_file {
+            Some(path) => match self.dir.read_to_string(path) {
+                Ok(contents) => IgnoreRules::parse(&contents),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => IgnoreRules::default(),
+                Err(e) => return Err(e),
+            },
+            None => IgnoreRules::default(),
+        };
+        let mut paths = vec![];
+        for pattern in &self.patterns {
+            let components = parse_pattern(pattern);
+            if components
+                .iter()
+                .all(|c| matches!(c, Component::Literal(_)))
+            {
+                paths.push(PathBuf::from(pattern));
+            } else {
+                self.walk(Path::new(""), &components, &ignore, &mut paths)?;
+            }
+        }
+        paths.sort();
+        paths.dedup();
+        Ok(paths)
+    }
+
+    /// Appends files below `base` matching `components` to `paths`.
+    fn walk(
+        &self,
+        base: &Path,
+        components: &[Component],
+        ignore: &IgnoreRules,
+        paths: &mut Vec<PathBuf>,
+    ) -> io::Result<()> {
+        let Some((first, rest)) = components.split_first() else {
+            return Ok(());
+        };
+        if let Component::Literal(name) = first {
+            let path = base.join(name);
+            if rest.is_empty() {
+                if self.dir.is_file(&path) && !ignore.is_ignored(&path, false) {
+                    paths.push(path);
+                }
+            } else if self.dir.is_dir(&path) && !ignore.is_ignored(&path, true) {
+                self.walk(&path, rest, ignore, paths)?;
+            }
+            return Ok(());
+        }
+        if matches!(first, Component::AnyDirs) {
+            // `**` matching no directories.
+            self.walk(base, rest, ignore, paths)?;
+        }
+        let entries = if base.as_os_str().is_empty() {
+            self.dir.entries()?
+        } else {
+            self.dir.read_dir(base)?
+        };
+        for entry in entries {
+            let entry = entry?;
+            let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+            let file_type = entry.file_type()?;
+            let path = base.join(&name);
+            if ignore.is_ignored(&path, file_type.is_dir()) {
+                continue;
+            }
+            match first {
+                Component::AnyDirs if file_type.is_dir() && !name.starts_with('.') => {
+                    self.walk(&path, components, ignore, paths)?
+                }
+                // A trailing `**` matches every file below it.
+                Component::AnyDirs
+                    if rest.is_empty() && file_type.is_file() && !name.starts_with('.') =>
+                {
+                    paths.push(path)
+                }
+                Component::Wildcard(pattern) if wildcard_match(pattern, &name) => {
+                    if rest.is_empty() {
+                        if file_type.is_file() {
+                            paths.push(path);
+                        }
+                    } else if file_type.is_dir() {
+                        self.walk(&path, rest, ignore, paths)?;
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Source text read from standard input, or any other `io::Read`.
+///
+/// The text is added to the `SourceCache` under `name`, which defaults to `<stdin>`.
+pub struct StdinSource {
+    pub name: PathBuf,
+    pub reader: Box<dyn io::Read>,
+}
+
+impl StdinSource {
+    /// Reads from the standard input of the process.
+    pub fn new() -> Self {
+        Self::from_reader(io::stdin())
+    }
+
+    /// Reads from `reader` in place of standard input.
+    pub fn from_reader(reader: impl io::Read + 'static) -> Self {
+        Self {
+            name: PathBuf::from("<stdin>"),
+            reader: Box::new(reader),
+        }
+    }
+
+    /// Sets the name the source is displayed under.
+    pub fn name(mut self, name: impl Into<PathBuf>) -> Self {
+        self.name = name.into();
+        self
+    }
+}
+
+impl Default for StdinSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Component {
+    Literal(String),
+    Wildcard(String),
+    /// `**`
+    AnyDirs,
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Component> {
+    pattern
+        .split('/')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .map(|c| match c {
+            "**" => Component::AnyDirs,
+            c if c.contains(['*', '?', '[']) => Component::Wildcard(c.to_string()),
+            c => Component::Literal(c.to_string()),
+        })
+        .collect()
+}
+
+/// Matches a single path component against a pattern containing `*`, `?` and `[...]`.
+pub(crate) fn wildcard_match(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    match_chars(&pattern, &name)
+}
+
+/// Matches `name` against `pattern`, backtracking only to the last `*`.
+fn match_chars(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // The pattern position after the last `*`, and the name position it resumes from.
+    let mut star = None;
+    while n < name.len() {
+        if pattern.get(p) == Some(&'*') {
+            p += 1;
+            star = Some((p, n));
+        } else if let Some(len) = match_char(&pattern[p..], name[n]) {
+            p += len;
+            n += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // Let the last `*` consume one more char and retry from there.
+            p = star_p;
+            n = star_n + 1;
+            star = Some((star_p, n));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Matches `c` against the `?`, `[...]` or literal at the start of `pattern`,
+/// returning the length of pattern it consumed.
+fn match_char(pattern: &[char], c: char) -> Option<usize> {
+    match pattern.first()? {
+        '?' => Some(1),
+        '[' => match class_match(&pattern[1..], Some(&c)) {
+            Some((true, class_len)) => Some(1 + class_len),
+            Some((false, _)) => None,
+            // An unterminated class matches `[` literally.
+            None => (c == '[').then_some(1),
+        },
+        literal => (*literal == c).then_some(1),
+    }
+}
+
+/// Matches `c` against the class following a `[`, returning whether it matched
+/// and the length of the class including the closing `]`.
+fn class_match(class: &[char], c: Option<&char>) -> Option<(bool, usize)> {
+    let negated = matches!(class.first(), Some('!' | '^'));
+    let start = usize::from(negated);
+    // The first member may be a `]`, so the search for the end starts after it.
+    let end = start + 1 + class.get(start + 1..)?.iter().position(|c| *c == ']')?;
+    let members = &class[start..end];
+    let mut matched = false;
+    if let Some(c) = c {
+        let mut i = 0;
+        while i < members.len() {
+            if i + 2 < members.len() && members[i + 1] == '-' {
+                matched |= (members[i]..=members[i + 2]).contains(c);
+                i += 3;
+            } else {
+                matched |= members[i] == *c;
+                i += 1;
+            }
+        }
+    }
+    Some((matched != negated, end + 1))
+}
+
+/// A subset of the `.gitignore` syntax.
+///
+/// Supports comments, `!` negation, a trailing `/` for directories only,
+/// and patterns containing a `/` being relative to the root.
+#[derive(Debug, Default)]
+struct IgnoreRules {
+    rules: Vec<IgnoreRule>,
+}
+
+#[derive(Debug)]
+struct IgnoreRule {
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+    components: Vec<Component>,
+}
+
+impl IgnoreRules {
+    fn parse(contents: &str) -> Self {
+        let rules = contents
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(line) => (true, line),
+                    None => (false, line),
+                };
+                let (dir_only, line) = match line.strip_suffix('/') {
+                    Some(line) => (true, line),
+                    None => (false, line),
+                };
+                IgnoreRule {
+                    negated,
+                    dir_only,
+                    anchored: line.contains('/'),
+                    components: parse_pattern(line),
+                }
+            })
+            .collect();
+        Self { rules }
+    }
+
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let components = path.iter().filter_map(|c| c.to_str()).collect::<Vec<_>>();
+        let mut ignored = false;
+        for rule in &self.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let matched = if rule.anchored {
+                match_components(&rule.components, &components)
+            } else {
+                components
+                    .last()
+                    .is_some_and(|name| match_components(&rule.components, &[name]))
+            };
+            if matched {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+}
+
+fn match_components(pattern: &[Component], path: &[&str]) -> bool {
+    match (pattern.split_first(), path.split_first()) {
+        (None, None) => true,
+        (Some((Component::AnyDirs, rest)), _) => {
+            (0..=path.len()).any(|skip| match_components(rest, &path[skip..]))
+        }
+        (Some((Component::Literal(literal), rest)), Some((name, path))) => {
+            literal == name && match_components(rest, path)
+        }
+        (Some((Component::Wildcard(pattern), rest)), Some((name, path))) => {
+            wildcard_match(pattern, name) && match_components(rest, path)
+        }
+        _ => false,
+    }
+}
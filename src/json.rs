@@ -0,0 +1,181 @@
+use crate::{
+    diagnostics::{buffered_diagnostics, BufferedDiagnostics, Diagnostic, DiagnosticsEnv},
+    line_index::ColumnUnit,
+    source::SourceCache,
+    tool::Tool,
+};
+use std::{fmt::Write as _, io};
+
+/// The version of the schema written by [JsonDiagnostics].
+///
+/// This is only incremented for changes which would break an existing consumer,
+/// such as removing or renaming a field. New fields may be added without
+/// incrementing it, consumers should ignore fields they do not recognise.
+pub const JSON_DIAGNOSTICS_VERSION: u32 = 1;
+
+/// A `Diagnostics` implementation writing one JSON object per line for each diagnostic.
+///
+/// Each line is an object of the following form, in emission order:
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "severity": "error",
+///   "message": "Duplicate rule",
+///   "code": "Y0001",
+///   "lint": null,
+///   "path": "grammar.y",
+///   "spans": [
+///     {
+///       "start": 20, "end": 23,
+///       "line_start": 3, "column_start": 1,
+///       "line_end": 3, "column_end": 4,
+///       "label": null
+///     }
+///   ]
+/// }
+/// ```
+///
+/// * `version` is [JSON_DIAGNOSTICS_VERSION].
+/// * `severity` is either `"error"` or `"warning"`, a warning denied by its
+///   lint level has the severity `"error"`.
+/// * `message` is the `Display` output of the diagnostic.
+/// * `code` is the [code](crate::Spanned::code) of the diagnostic or `null`.
+/// * `lint` is the [lint name](crate::Spanned::lint_name) of a warning, or `null` for errors.
+/// * `path` is the path of the source the diagnostic refers to,
+///   or `null` if the diagnostic has no `SourceId` or it is not in the `SourceCache`.
+/// * `spans` are in the order given by [Spanned::spans](crate::Spanned::spans),
+///   `start` and `end` are byte offsets. Lines and columns are one based,
+///   columns count chars, `column_end` is exclusive. They are `null` if the
+///   offset does not lie within the source. `label` is the text given by
+///   [Spanned::format_span](crate::Spanned::format_span) or `null`.
+///
+/// Objects are written without insignificant whitespace, one per line.
+/// Diagnostics are buffered until `no_more_data`, which writes them and flushes the writer.
+pub struct JsonDiagnostics<X: Tool, W: io::Write = io::Stdout> {
+    writer: W,
+    pending: Vec<Diagnostic<X>>,
+}
+
+impl<X: Tool, W: io::Write> JsonDiagnostics<X, W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            pending: vec![],
+        }
+    }
+    pub fn writer(&self) -> &W {
+        &self.writer
+    }
+    pub fn into_writer(self) -> W {
+        self.writer
+    }
+}
+
+impl<X: Tool> JsonDiagnostics<X, io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+buffered_diagnostics!(JsonDiagnostics);
+
+impl<X: Tool, W: io::Write> BufferedDiagnostics<X> for JsonDiagnostics<X, W> {
+    fn pending(&mut self) -> &mut Vec<Diagnostic<X>> {
+        &mut self.pending
+    }
+    fn write_diagnostics(&mut self, diagnostics: Vec<Diagnostic<X>>, env: &DiagnosticsEnv<'_, X>) {
+        for diagnostic in diagnostics {
+            let json = diagnostic_to_json(&diagnostic, env.source_cache());
+            let _ = writeln!(self.writer, "{json}");
+        }
+        let _ = self.writer.flush();
+    }
+}
+
+/// Formats a diagnostic as a single line JSON object, without a trailing newline.
+///
+/// See [JsonDiagnostics] for the schema.
+pub fn diagnostic_to_json<X: Tool>(
+    diagnostic: &Diagnostic<X>,
+    source_cache: &SourceCache,
+) -> String {
+    let mut out = String::new();
+    let path = diagnostic
+        .source_id()
+        .and_then(|src_id| source_cache.path_for_id(src_id));
+    write!(
+        out,
+        "{{\"version\":{},\"severity\":\"{}\",\"message\":",
+        JSON_DIAGNOSTICS_VERSION,
+        diagnostic.severity()
+    )
+    .unwrap();
+    push_json_string(&mut out, &diagnostic.to_string());
+    out.push_str(",\"code\":");
+    match diagnostic.code() {
+        Some(code) => push_json_string(&mut out, code),
+        None => out.push_str("null"),
+    }
+    out.push_str(",\"lint\":");
+    match diagnostic.lint_name() {
+        Some(lint) => push_json_string(&mut out, lint),
+        None => out.push_str("null"),
+    }
+    out.push_str(",\"path\":");
+    match path {
+        Some(path) => push_json_string(&mut out, &path.to_string_lossy()),
+        None => out.push_str("null"),
+    }
+    out.push_str(",\"spans\":[");
+    for (idx, span) in diagnostic.spans().iter().enumerate() {
+        if idx != 0 {
+            out.push(',');
+        }
+        let line_col = |offset| {
+            diagnostic
+                .source_id()
+                .and_then(|src_id| source_cache.line_col(src_id, offset, ColumnUnit::Char))
+        };
+        let (start, end) = (line_col(span.start()), line_col(span.end()));
+        write!(
+            out,
+            "{{\"start\":{},\"end\":{},\"line_start\":{},\"column_start\":{},\"line_end\":{},\"column_end\":{},\"label\":",
+            span.start(),
+            span.end(),
+            json_number(start.map(|lc| lc.line + 1)),
+            json_number(start.map(|lc| lc.col + 1)),
+            json_number(end.map(|lc| lc.line + 1)),
+            json_number(end.map(|lc| lc.col + 1)),
+        )
+        .unwrap();
+        match diagnostic.span_label(idx) {
+            Some(label) => push_json_string(&mut out, &label),
+            None => out.push_str("null"),
+        }
+        out.push('}');
+    }
+    out.push_str("]}");
+    out
+}
+
+fn json_number(n: Option<usize>) -> String {
+    n.map_or_else(|| "null".to_string(), |n| n.to_string())
+}
+
+/// Appends `s` to `out` as a quoted and escaped JSON string.
+pub(crate) fn push_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
@@ -2,15 +2,49 @@ pub use dir_view;
 /// I don't know how I feel about this, but it works.
 use std::{fmt, sync::atomic::AtomicUsize};
 
+#[cfg(feature = "archive")]
+mod archive;
+mod build_script;
+#[cfg(feature = "cache")]
+mod cache;
+mod code;
 mod default_impls;
+mod depfile;
 mod diagnostics;
+mod diff;
 mod driver;
+#[cfg(feature = "cache")]
+mod encode;
+mod fix;
+mod graph;
+mod include;
+mod incremental;
+mod input;
+mod json;
+mod line_index;
+mod lint;
+mod output;
+mod provider;
+mod quickfix;
+mod render;
+mod sarif;
 mod source;
 mod tool;
+#[cfg(all(feature = "watch", target_os = "linux"))]
+mod watch;
 
+#[cfg(feature = "archive")]
+pub use crate::archive::*;
+#[cfg(feature = "cache")]
+pub use crate::{cache::*, encode::*};
+#[cfg(all(feature = "watch", target_os = "linux"))]
+pub use crate::watch::*;
 pub use {
-    crate::default_impls::*, crate::diagnostics::*, crate::driver::*, crate::source::*,
-    crate::tool::*,
+    crate::build_script::*, crate::code::*, crate::default_impls::*, crate::depfile::*,
+    crate::diagnostics::*, crate::diff::*, crate::driver::*, crate::fix::*, crate::graph::*,
+    crate::include::*, crate::incremental::*, crate::input::*, crate::json::*, crate::line_index::*,
+    crate::lint::*, crate::output::*, crate::provider::*, crate::quickfix::*, crate::render::*,
+    crate::sarif::*, crate::source::*, crate::tool::*,
 };
 
 #[cfg(test)]
@@ -32,17 +66,51 @@ pub trait Args {
     type OptionalArgs: Default;
 }
 
-pub(crate) static NEXT_SOURCE_ID: AtomicUsize = AtomicUsize::new(0);
+/// The identity of the next `SourceCache`.
+pub(crate) static NEXT_SOURCE_CACHE_ID: AtomicUsize = AtomicUsize::new(0);
 
 /// A `Span` records what portion of the user's input something (e.g. a lexeme or production)
 /// references (i.e. the `Span` doesn't hold a reference / copy of the actual input).
-#[derive(Debug)]
-#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Span {
     start: usize,
     end: usize,
 }
 
+impl Span {
+    /// Create a new span starting at byte `start` and ending at byte `end`.
+    ///
+    /// # Panics
+    ///
+    /// If `end` is less than `start`.
+    pub fn new(start: usize, end: usize) -> Self {
+        if end < start {
+            panic!("Span starts ({}) after it ends ({})!", start, end);
+        }
+        Self { start, end }
+    }
+
+    /// Byte offset of the start of the span.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Byte offset of the end of the span.
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// Length in bytes of the span.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Returns `true` if this `Span` covers 0 bytes, or `false` otherwise.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 /// Implemented for errors and warnings to provide access to their spans.
 pub trait Spanned: std::fmt::Display {
     type SpansKind;
@@ -53,7 +121,36 @@ pub trait Spanned: std::fmt::Display {
     fn spans(&self) -> &[Span];
     /// Returns the `SpansKind` associated with this error.
     fn spanskind(&self) -> Self::SpansKind;
-    fn format_span(self, idx: usize) -> Option<impl fmt::Display>;
+    /// Returns a label for the span at `idx`, if it has one.
+    ///
+    /// Renderers call this for every span in [spans](Self::spans).
+    fn format_span(&self, idx: usize) -> Option<impl fmt::Display>;
+    /// The stable code of this diagnostic, e.g. `Y0042`, if it has one.
+    ///
+    /// Codes give users something searchable while keeping messages short,
+    /// they are registered along with an explanation in [Tool::ERROR_CODES].
+    fn code(&self) -> Option<&'static str> {
+        None
+    }
+    /// The name of the lint of a warning, e.g. `unused_rule`.
+    ///
+    /// The name is used to configure the [LintLevel] of a warning through
+    /// [LintLevels], and should not change between releases of a tool.
+    /// Defaults to the name of the implementing type, without its module path.
+    fn lint_name(&self) -> &'static str {
+        short_type_name::<Self>()
+    }
+    /// Suggested replacements within the source of this diagnostic.
+    fn suggestions(&self) -> &[Suggestion] {
+        &[]
+    }
+}
+
+/// The name of `T` without its module path or generic arguments.
+pub(crate) fn short_type_name<T: ?Sized>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name)
 }
 
 /// A pair of required and optional parameters.
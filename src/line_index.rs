@@ -0,0 +1,139 @@
+use crate::Span;
+
+/// The unit a column is measured in.
+///
+/// Editors disagree on what a column is, e.g. the language server protocol
+/// defaults to UTF-16 code units, while terminals generally count chars.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ColumnUnit {
+    /// Columns are byte offsets from the start of the line.
+    Byte,
+    /// Columns count `char`s (unicode scalar values) from the start of the line.
+    Char,
+    /// Columns count UTF-16 code units from the start of the line.
+    Utf16,
+}
+
+/// A zero based line and column.
+///
+/// The unit of `col` is given by the [ColumnUnit] used to produce it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LineCol {
+    pub line: usize,
+    pub col: usize,
+}
+
+/// An index of the byte offsets at which each line of a source string starts.
+///
+/// Lines are terminated by `\n`, a preceding `\r` is considered part of the
+/// line terminator rather than the line.
+///
+/// The index does not hold a copy of the source, so methods which need to
+/// inspect the text take it as an argument, that text must be the same
+/// text the index was built from.
+#[derive(Debug)]
+pub struct LineIndex {
+    line_starts: Vec<usize>,
+    len: usize,
+}
+
+impl LineIndex {
+    pub fn new(src: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self {
+            line_starts,
+            len: src.len(),
+        }
+    }
+
+    /// The number of lines, a source ending with a newline has an empty last line.
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Returns the line containing the byte `offset`.
+    ///
+    /// An offset equal to the length of the source is on the last line.
+    pub fn line_of(&self, offset: usize) -> Option<usize> {
+        if offset > self.len {
+            return None;
+        }
+        Some(self.line_starts.partition_point(|&start| start <= offset) - 1)
+    }
+
+    /// Returns the span of `line` excluding its line terminator.
+    pub fn line_span(&self, src: &str, line: usize) -> Option<Span> {
+        let start = *self.line_starts.get(line)?;
+        let end = self.line_starts.get(line + 1).copied().unwrap_or(self.len);
+        let text = &src[start..end];
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        Some(Span::new(start, start + text.len()))
+    }
+
+    /// Returns the text of `line` excluding its line terminator.
+    pub fn line_text<'a>(&self, src: &'a str, line: usize) -> Option<&'a str> {
+        let span = self.line_span(src, line)?;
+        Some(&src[span.start()..span.end()])
+    }
+
+    /// Converts a byte offset to a line and column.
+    ///
+    /// An offset within a line terminator is at the end of its line, so that
+    /// [offset](Self::offset) maps the column back to the start of the terminator.
+    ///
+    /// Returns `None` if `offset` is past the end of `src` or not on a `char` boundary.
+    pub fn line_col(&self, src: &str, offset: usize, unit: ColumnUnit) -> Option<LineCol> {
+        if !src.is_char_boundary(offset) {
+            return None;
+        }
+        let line = self.line_of(offset)?;
+        let span = self.line_span(src, line)?;
+        let prefix = &src[span.start()..offset.min(span.end())];
+        let col = match unit {
+            ColumnUnit::Byte => prefix.len(),
+            ColumnUnit::Char => prefix.chars().count(),
+            ColumnUnit::Utf16 => prefix.encode_utf16().count(),
+        };
+        Some(LineCol { line, col })
+    }
+
+    /// Converts a line and column back to a byte offset.
+    ///
+    /// The column may point one past the last character of the line,
+    /// but not into the line terminator or beyond it. Returns `None` for
+    /// columns which do not fall on a `char` boundary, including a UTF-16
+    /// column that would split a surrogate pair.
+    pub fn offset(&self, src: &str, line_col: LineCol, unit: ColumnUnit) -> Option<usize> {
+        let span = self.line_span(src, line_col.line)?;
+        let text = &src[span.start()..span.end()];
+        let col = match unit {
+            ColumnUnit::Byte => text.is_char_boundary(line_col.col).then_some(line_col.col),
+            ColumnUnit::Char => text
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain(std::iter::once(text.len()))
+                .nth(line_col.col),
+            ColumnUnit::Utf16 => {
+                let mut units = 0;
+                let mut found = None;
+                for (i, c) in text
+                    .char_indices()
+                    .chain(std::iter::once((text.len(), '\0')))
+                {
+                    if units == line_col.col {
+                        found = Some(i);
+                        break;
+                    } else if units > line_col.col {
+                        break;
+                    }
+                    units += c.len_utf16();
+                }
+                found
+            }
+        }?;
+        Some(span.start() + col)
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+};
+
+/// How a warning is handled by the `DiagnosticsEmitter`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LintLevel {
+    /// The warning is dropped before it reaches `Diagnostics`.
+    Allow,
+    /// The warning is emitted as a warning.
+    #[default]
+    Warn,
+    /// The warning is emitted as an error, and an error is observed.
+    Deny,
+    /// Like `Deny`, but the level cannot be changed afterwards.
+    Forbid,
+}
+
+/// Maps lint names to their [LintLevel].
+///
+/// Lints which have not been given a level are at `LintLevel::Warn`.
+#[derive(Debug, Default, Clone)]
+pub struct LintLevels {
+    levels: HashMap<String, LintLevel>,
+    deny_warnings: bool,
+}
+
+impl LintLevels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the level of the lint `name`.
+    ///
+    /// Returns `false` and leaves the level unchanged if the lint was previously
+    /// set to `LintLevel::Forbid`, otherwise returns `true`.
+    pub fn set(&mut self, name: impl Into<String>, level: LintLevel) -> bool {
+        let name = name.into();
+        match self.levels.get(&name) {
+            Some(LintLevel::Forbid) => level == LintLevel::Forbid,
+            _ => {
+                self.levels.insert(name, level);
+                true
+            }
+        }
+    }
+
+    /// Builder style variant of [set](Self::set).
+    pub fn with(mut self, name: impl Into<String>, level: LintLevel) -> Self {
+        self.set(name, level);
+        self
+    }
+
+    /// When `true` every lint at `LintLevel::Warn` is treated as `LintLevel::Deny`.
+    ///
+    /// Lints which have been explicitly allowed are still allowed,
+    /// this is intended for CI builds which should fail on any warning.
+    pub fn deny_warnings(mut self, deny_warnings: bool) -> Self {
+        self.deny_warnings = deny_warnings;
+        self
+    }
+
+    /// The level in effect for the lint `name`.
+    pub fn level(&self, name: &str) -> LintLevel {
+        match self.levels.get(name).copied().unwrap_or_default() {
+            LintLevel::Warn if self.deny_warnings => LintLevel::Deny,
+            level => level,
+        }
+    }
+}
+
+impl Hash for LintLevels {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut levels = self.levels.iter().collect::<Vec<_>>();
+        levels.sort();
+        levels.hash(state);
+        self.deny_warnings.hash(state);
+    }
+}
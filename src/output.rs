@@ -0,0 +1,246 @@
+use crate::{
+    diff::unified_diff,
+    driver::DriverError,
+    source::{Session, SourceCache, SourceId},
+};
+use dir_view::DirView;
+use std::{
+    any::Any,
+    io, path,
+    sync::atomic::{self, AtomicUsize},
+};
+
+/// What the driver does with the sources a tool added to the `SourceCache`.
+pub enum OutputMode {
+    /// Writes a unified diff between the file on disk and each added source.
+    Diff(Box<dyn io::Write>),
+    /// Fails with `DriverError::StaleSources` if any added source differs from the
+    /// file on disk, or the file does not exist. Nothing is written.
+    Check,
+    /// Writes each added source to its path within the dir, which must be writable.
+    ///
+    /// Files are replaced atomically by writing a temporary file and renaming it,
+    /// files whose contents are unchanged are left untouched to preserve their mtime.
+    Write,
+}
+
+/// Arguments for handling the sources a tool adds to the `SourceCache`.
+///
+/// After `tool_init`, each source the tool added whose `SourceKind`
+/// is selected by the filter is handled according to `mode`,
+/// with paths relative to `dir`.
+pub struct OutputArgs {
+    pub dir: DirView,
+    pub mode: OutputMode,
+    /// A `Box<dyn Fn(&SourceKind) -> bool>`, checked against the tool's `SourceKind`.
+    filter: Box<dyn Any>,
+}
+
+type KindFilter<SourceKind> = Box<dyn Fn(&SourceKind) -> bool>;
+
+impl OutputArgs {
+    /// Handles sources added by the tool whose kind satisfies `filter`.
+    ///
+    /// `SourceKind` must be the `SourceKind` of the tool being run, otherwise
+    /// `driver_init` returns `DriverError::SourceKindMismatch`.
+    pub fn new<SourceKind: 'static>(
+        dir: DirView,
+        mode: OutputMode,
+        filter: impl Fn(&SourceKind) -> bool + 'static,
+    ) -> Self {
+        let filter: KindFilter<SourceKind> = Box::new(filter);
+        Self {
+            dir,
+            mode,
+            filter: Box::new(filter),
+        }
+    }
+
+    /// Handles sources added by the tool of the kind `source_kind`.
+    pub fn for_kind<SourceKind: PartialEq + 'static>(
+        dir: DirView,
+        mode: OutputMode,
+        source_kind: SourceKind,
+    ) -> Self {
+        Self::new(dir, mode, move |kind: &SourceKind| *kind == source_kind)
+    }
+
+    /// The sources added by the tool which are selected by the filter.
+    pub(crate) fn selected<SourceKind: 'static>(
+        &self,
+        session: &Session<SourceKind>,
+    ) -> Result<Vec<SourceId>, DriverError> {
+        let filter = self
+            .filter
+            .downcast_ref::<KindFilter<SourceKind>>()
+            .ok_or(DriverError::SourceKindMismatch)?;
+        Ok(session
+            .added_source_ids()
+            .iter()
+            .copied()
+            .filter(|src_id| session.source_kinds.get(src_id).is_some_and(filter))
+            .collect())
+    }
+
+    /// Performs the `OutputMode` for the selected sources of the session.
+    pub(crate) fn run<SourceKind: 'static>(
+        &mut self,
+        session: &Session<SourceKind>,
+        source_cache: &SourceCache,
+    ) -> Result<(), DriverError> {
+        let selected = self.selected(session)?;
+        match &mut self.mode {
+            OutputMode::Diff(out) => {
+                for src_id in selected {
+                    write_disk_diff(&self.dir, source_cache, src_id, out)?;
+                }
+                out.flush()?;
+            }
+            OutputMode::Check => {
+                let mut stale = vec![];
+                for src_id in selected {
+                    if let Some((path, src)) = path_and_source(source_cache, src_id) {
+                        if read_existing(&self.dir, path)?.as_deref() != Some(src.as_bytes()) {
+                            stale.push(path.to_path_buf());
+                        }
+                    }
+                }
+                if !stale.is_empty() {
+                    return Err(DriverError::StaleSources(stale));
+                }
+            }
+            OutputMode::Write => {
+                for src_id in selected {
+                    if let Some((path, src)) = path_and_source(source_cache, src_id) {
+                        write_if_changed(&self.dir, path, src)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the file at `path` within `dir`, returning `None` if it doesn't exist.
+pub(crate) fn read_existing(dir: &DirView, path: &path::Path) -> io::Result<Option<Vec<u8>>> {
+    match dir.read(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Distinguishes the temporary files of `write_atomic` calls within this process.
+static NEXT_TMP_FILE: AtomicUsize = AtomicUsize::new(0);
+
+/// Atomically replaces the file at `path` within `dir` with `contents` if they differ.
+///
+/// Returns whether the file was written.
+pub(crate) fn write_if_changed(
+    dir: &DirView,
+    path: &path::Path,
+    contents: &str,
+) -> Result<bool, DriverError> {
+    let write_error = |source| DriverError::Write {
+        path: path.to_path_buf(),
+        source,
+    };
+    if read_existing(dir, path).map_err(write_error)?.as_deref() == Some(contents.as_bytes()) {
+        return Ok(false);
+    }
+    write_atomic(dir, path, contents.as_bytes()).map_err(write_error)?;
+    Ok(true)
+}
+
+/// Writes `contents` to a temporary file next to `path` within `dir`, then renames it to `path`.
+///
+/// The temporary file name includes the process id and a per-call counter, so
+/// concurrent writes of the same path from different threads don't collide.
+pub(crate) fn write_atomic(dir: &DirView, path: &path::Path, contents: &[u8]) -> io::Result<()> {
+    let file_name = path.file_name().ok_or(io::ErrorKind::InvalidInput)?;
+    let parent = path.parent().unwrap_or(path::Path::new(""));
+    if !parent.as_os_str().is_empty() {
+        dir.create_dir_all(parent)?;
+    }
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(file_name);
+    let count = NEXT_TMP_FILE.fetch_add(1, atomic::Ordering::Relaxed);
+    tmp_name.push(format!(".{}.{count}.tmp", std::process::id()));
+    let tmp_path = parent.join(tmp_name);
+    let result = dir
+        .write(&tmp_path, contents)
+        .and_then(|()| dir.rename(&tmp_path, dir, path));
+    if result.is_err() {
+        let _ = dir.remove_file(&tmp_path);
+    }
+    result
+}
+
+fn path_and_source(source_cache: &SourceCache, src_id: SourceId) -> Option<(&path::Path, &str)> {
+    Some((
+        source_cache.path_for_id(src_id)?,
+        source_cache.source_for_id(src_id)?,
+    ))
+}
+
+/// Writes a diff between the file at the path of `src_id` within `dir`, and the source text.
+///
+/// Returns whether the file differed from the source.
+fn write_disk_diff(
+    dir: &DirView,
+    source_cache: &SourceCache,
+    src_id: SourceId,
+    out: &mut dyn io::Write,
+) -> Result<bool, DriverError> {
+    let Some((path, src)) = path_and_source(source_cache, src_id) else {
+        return Ok(false);
+    };
+    let on_disk =
+        read_existing(dir, path)?.map(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+    let old_name = match on_disk {
+        Some(_) => format!("a/{}", path.display()),
+        None => "/dev/null".to_string(),
+    };
+    let diff = unified_diff(
+        &old_name,
+        on_disk.as_deref().unwrap_or_default(),
+        &format!("b/{}", path.display()),
+        src,
+    );
+    out.write_all(diff.as_bytes())?;
+    Ok(!diff.is_empty())
+}
+
+/// Writes a unified diff for every fixed and generated source in `session`.
+///
+/// Fixed sources are compared against the source they were fixed from,
+/// sources added by the tool whose kind satisfies `filter` are compared
+/// against the file at their path in `dir`. Returns the number of sources
+/// which differ. The output is suitable for `patch -p1` from `dir`.
+pub fn write_session_diffs<SourceKind>(
+    session: &Session<SourceKind>,
+    source_cache: &SourceCache,
+    dir: &DirView,
+    filter: impl Fn(&SourceKind) -> bool,
+    out: &mut dyn io::Write,
+) -> Result<usize, DriverError> {
+    let mut differing = 0;
+    for fixed_id in session.fixed_source_ids() {
+        let diff = session
+            .fixed_from(*fixed_id)
+            .and_then(|original| crate::diff::source_diff(source_cache, original, *fixed_id))
+            .unwrap_or_default();
+        if !diff.is_empty() {
+            differing += 1;
+            out.write_all(diff.as_bytes())?;
+        }
+    }
+    for src_id in session.added_source_ids() {
+        if session.source_kinds.get(src_id).is_some_and(&filter)
+            && write_disk_diff(dir, source_cache, *src_id, out)?
+        {
+            differing += 1;
+        }
+    }
+    Ok(differing)
+}
@@ -0,0 +1,172 @@
+use crate::input::StdinSource;
+use dir_view::DirView;
+use std::{
+    collections::BTreeMap,
+    io::{self, Read as _},
+    path::{Path, PathBuf},
+};
+
+/// A source of text which the driver loads into the `SourceCache`.
+///
+/// Implemented for `DirView`, [MemorySources], and [StdinSource].
+pub trait SourceProvider {
+    /// Reads the source text at `path`.
+    ///
+    /// Returns an `io::ErrorKind::NotFound` error if there is no source at `path`,
+    /// and an `io::ErrorKind::InvalidData` error if it is not valid UTF-8.
+    fn read_source(&mut self, path: &Path) -> io::Result<String>;
+
+    /// The file read for the source at `path`, recorded as a dependency for build systems.
+    ///
+    /// Providers of sources which are not read from files return `None`.
+    fn file_path(&self, _path: &Path) -> Option<PathBuf> {
+        None
+    }
+}
+
+impl<P: SourceProvider + ?Sized> SourceProvider for &mut P {
+    fn read_source(&mut self, path: &Path) -> io::Result<String> {
+        (**self).read_source(path)
+    }
+
+    fn file_path(&self, path: &Path) -> Option<PathBuf> {
+        (**self).file_path(path)
+    }
+}
+
+impl<P: SourceProvider + ?Sized> SourceProvider for Box<P> {
+    fn read_source(&mut self, path: &Path) -> io::Result<String> {
+        (**self).read_source(path)
+    }
+
+    fn file_path(&self, path: &Path) -> Option<PathBuf> {
+        (**self).file_path(path)
+    }
+}
+
+impl SourceProvider for DirView {
+    fn read_source(&mut self, path: &Path) -> io::Result<String> {
+        let mut source = String::new();
+        self.open(path)?.read_to_string(&mut source)?;
+        Ok(source)
+    }
+
+    /// The path relative to the `DirView`.
+    fn file_path(&self, path: &Path) -> Option<PathBuf> {
+        Some(path.to_path_buf())
+    }
+}
+
+/// Reads the whole of the reader if `path` is the name of the source.
+///
+/// A reader can only be read once, subsequent reads return an empty string.
+impl SourceProvider for StdinSource {
+    fn read_source(&mut self, path: &Path) -> io::Result<String> {
+        if path != self.name {
+            return Err(not_found(path));
+        }
+        let mut source = String::new();
+        self.reader.read_to_string(&mut source)?;
+        Ok(source)
+    }
+}
+
+/// An in-memory map from paths to source text.
+#[derive(Debug, Default, Clone)]
+pub struct MemorySources {
+    sources: BTreeMap<PathBuf, String>,
+}
+
+impl MemorySources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces the source at `path`.
+    pub fn insert(&mut self, path: impl Into<PathBuf>, source: impl Into<String>) {
+        self.sources.insert(path.into(), source.into());
+    }
+
+    /// Builder style variant of [insert](Self::insert).
+    pub fn with(mut self, path: impl Into<PathBuf>, source: impl Into<String>) -> Self {
+        self.insert(path, source);
+        self
+    }
+
+    /// The source at `path`, if any.
+    pub fn get(&self, path: &Path) -> Option<&str> {
+        self.sources.get(path).map(String::as_str)
+    }
+
+    /// The paths of every source, in sorted order.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.sources.keys().map(PathBuf::as_path)
+    }
+}
+
+impl SourceProvider for MemorySources {
+    fn read_source(&mut self, path: &Path) -> io::Result<String> {
+        self.sources
+            .get(path)
+            .cloned()
+            .ok_or_else(|| not_found(path))
+    }
+}
+
+/// A `SourceProvider` where sources in `overlay` shadow those of `inner`.
+///
+/// Intended for editors, where unsaved buffers are newer than the files on disk.
+/// Paths not in the overlay are read from `inner`, and the overlay does not
+/// add any paths to those matched by glob patterns.
+pub struct Overlay<P> {
+    pub overlay: MemorySources,
+    pub inner: P,
+}
+
+impl<P> Overlay<P> {
+    pub fn new(overlay: MemorySources, inner: P) -> Self {
+        Self { overlay, inner }
+    }
+}
+
+impl<P: SourceProvider> SourceProvider for Overlay<P> {
+    fn read_source(&mut self, path: &Path) -> io::Result<String> {
+        match self.overlay.get(path) {
+            Some(source) => Ok(source.to_string()),
+            None => self.inner.read_source(path),
+        }
+    }
+
+    /// The file of the inner provider, which the overlay stands in for.
+    fn file_path(&self, path: &Path) -> Option<PathBuf> {
+        self.inner.file_path(path)
+    }
+}
+
+/// Paths to load through a `SourceProvider`.
+pub struct ProvidedSources {
+    pub provider: Box<dyn SourceProvider>,
+    pub paths: Vec<PathBuf>,
+}
+
+impl ProvidedSources {
+    pub fn new(provider: impl SourceProvider + 'static) -> Self {
+        Self {
+            provider: Box::new(provider),
+            paths: vec![],
+        }
+    }
+
+    /// Adds a path to load, sources are loaded in the order they are added.
+    pub fn with(mut self, path: impl Into<PathBuf>) -> Self {
+        self.paths.push(path.into());
+        self
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("No source for {}", path.display()),
+    )
+}
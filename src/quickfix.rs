@@ -0,0 +1,110 @@
+use crate::{
+    diagnostics::{
+        buffered_diagnostics, BufferedDiagnostics, Diagnostic, DiagnosticsEnv, Severity,
+    },
+    line_index::ColumnUnit,
+    source::{SourceArtifact, SourceCache},
+    tool::Tool,
+    Spanned,
+};
+use std::io;
+
+/// Formats a diagnostic as compiler style `path:line:col: severity: message` lines.
+///
+/// One line is produced for every span, lines and columns are one based,
+/// and columns count chars. When a span has a label from
+/// [Spanned::format_span] it is appended to the message in parentheses.
+/// A code is appended to the severity in brackets, as in `error[Y0001]: message`.
+/// Newlines in messages are replaced with spaces, so that each entry is one line.
+///
+/// Diagnostics without spans produce a single line, omitting the parts
+/// which can't be resolved, e.g. `path: severity: message` when a span
+/// lies outside of the source, or `severity: message` when there is no source.
+pub fn quickfix_lines<E: Spanned + SourceArtifact>(
+    severity: Severity,
+    diagnostic: &E,
+    source_cache: &SourceCache,
+) -> Vec<String> {
+    let message = diagnostic.to_string().replace(['\r', '\n'], " ");
+    let severity = match diagnostic.code() {
+        Some(code) => format!("{severity}[{code}]"),
+        None => severity.to_string(),
+    };
+    let src_id = diagnostic.source_id();
+    let path = src_id.and_then(|src_id| source_cache.path_for_id(src_id));
+    let Some(path) = path else {
+        return vec![format!("{severity}: {message}")];
+    };
+    let spans = diagnostic.spans();
+    if spans.is_empty() {
+        return vec![format!("{}: {severity}: {message}", path.display())];
+    }
+    spans
+        .iter()
+        .enumerate()
+        .map(|(idx, span)| {
+            let location = src_id
+                .and_then(|src_id| source_cache.line_col(src_id, span.start(), ColumnUnit::Char))
+                .map(|lc| format!("{}:{}:{}", path.display(), lc.line + 1, lc.col + 1))
+                .unwrap_or_else(|| path.display().to_string());
+            match diagnostic.format_span(idx) {
+                Some(label) => format!("{location}: {severity}: {message} ({label})"),
+                None => format!("{location}: {severity}: {message}"),
+            }
+        })
+        .collect()
+}
+
+/// A `Diagnostics` implementation writing [quickfix_lines] for each diagnostic.
+///
+/// This is the format understood by vim's quickfix list, emacs `compile-mode`,
+/// and many CI log parsers. Diagnostics are buffered until `no_more_data`,
+/// which writes them in emission order and flushes the writer.
+pub struct QuickfixDiagnostics<X: Tool, W: io::Write = io::Stderr> {
+    writer: W,
+    pending: Vec<Diagnostic<X>>,
+}
+
+impl<X: Tool> QuickfixDiagnostics<X, io::Stderr> {
+    pub fn stderr() -> Self {
+        Self::new(io::stderr())
+    }
+}
+
+impl<X: Tool, W: io::Write> QuickfixDiagnostics<X, W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            pending: vec![],
+        }
+    }
+    pub fn writer(&self) -> &W {
+        &self.writer
+    }
+    pub fn into_writer(self) -> W {
+        self.writer
+    }
+}
+
+buffered_diagnostics!(QuickfixDiagnostics);
+
+impl<X: Tool, W: io::Write> BufferedDiagnostics<X> for QuickfixDiagnostics<X, W> {
+    fn pending(&mut self) -> &mut Vec<Diagnostic<X>> {
+        &mut self.pending
+    }
+    fn write_diagnostics(&mut self, diagnostics: Vec<Diagnostic<X>>, env: &DiagnosticsEnv<'_, X>) {
+        for diagnostic in diagnostics {
+            let lines = match &diagnostic {
+                Diagnostic::Error(e) => quickfix_lines(Severity::Error, e, env.source_cache()),
+                Diagnostic::Warning(w) => quickfix_lines(Severity::Warning, w, env.source_cache()),
+                Diagnostic::DeniedWarning(w) => {
+                    quickfix_lines(Severity::Error, w, env.source_cache())
+                }
+            };
+            for line in lines {
+                let _ = writeln!(self.writer, "{line}");
+            }
+        }
+        let _ = self.writer.flush();
+    }
+}
@@ -0,0 +1,311 @@
+use crate::{
+    diagnostics::{
+        buffered_diagnostics, BufferedDiagnostics, Diagnostic, DiagnosticsEnv, Severity,
+    },
+    line_index::{ColumnUnit, LineCol},
+    source::{SourceArtifact, SourceCache},
+    tool::Tool,
+    Span, Spanned,
+};
+use std::io::{self, IsTerminal};
+
+/// When to use colour in terminal output.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Use colour if the output stream is a terminal, `NO_COLOR` is not set,
+    /// and `TERM` is not `dumb`.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Decides whether output written to `stream` should be coloured.
+    pub fn should_color(self, stream: &impl IsTerminal) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                stream.is_terminal()
+                    && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                    && std::env::var_os("TERM").is_none_or(|term| term != "dumb")
+            }
+        }
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const BOLD_RED: &str = "\x1b[1;31m";
+const BOLD_YELLOW: &str = "\x1b[1;33m";
+const BOLD_BLUE: &str = "\x1b[1;34m";
+
+/// Renders `Spanned` diagnostics in the style of rustc.
+///
+/// ```text
+/// error[Y0001]: Duplicate rule
+///  --> grammar.y:3:1
+///   |
+/// 3 | foo: 'c';
+///   | ^^^
+/// 1 | foo: 'a';
+///   | --- Duplicate
+/// ```
+///
+/// The code of the diagnostic, if any, follows its severity.
+/// The first span is underlined with `^`, the remaining spans with `-`,
+/// each followed by its label from [Spanned::format_span].
+/// Columns are counted in chars and displayed one based.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Renderer {
+    color: bool,
+}
+
+impl Renderer {
+    pub fn new(color: bool) -> Self {
+        Self { color }
+    }
+
+    fn paint(&self, style: &'static str, text: &str) -> String {
+        if self.color {
+            format!("{style}{text}{RESET}")
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn severity_style(severity: Severity) -> &'static str {
+        match severity {
+            Severity::Error => BOLD_RED,
+            Severity::Warning => BOLD_YELLOW,
+        }
+    }
+
+    /// Renders a buffered `Diagnostic`.
+    pub fn render_diagnostic<X: Tool>(
+        &self,
+        diagnostic: &Diagnostic<X>,
+        source_cache: &SourceCache,
+        out: &mut impl io::Write,
+    ) -> io::Result<()> {
+        match diagnostic {
+            Diagnostic::Error(e) => self.render(Severity::Error, e, source_cache, out),
+            Diagnostic::Warning(w) => self.render(Severity::Warning, w, source_cache, out),
+            Diagnostic::DeniedWarning(w) => {
+                self.render(Severity::Error, w, source_cache, out)?;
+                writeln!(
+                    out,
+                    "{} note: `{}` is denied",
+                    self.paint(BOLD_BLUE, "  ="),
+                    w.lint_name()
+                )
+            }
+        }
+    }
+
+    /// Renders any error or warning, resolving its spans through `source_cache`.
+    pub fn render<E: Spanned + SourceArtifact>(
+        &self,
+        severity: Severity,
+        diagnostic: &E,
+        source_cache: &SourceCache,
+        out: &mut impl io::Write,
+    ) -> io::Result<()> {
+        let severity_style = Self::severity_style(severity);
+        let severity_and_code = match diagnostic.code() {
+            Some(code) => format!("{severity}[{code}]"),
+            None => severity.to_string(),
+        };
+        writeln!(
+            out,
+            "{}{}",
+            self.paint(severity_style, &severity_and_code),
+            self.paint(BOLD, &format!(": {diagnostic}"))
+        )?;
+        let Some(src_id) = diagnostic.source_id() else {
+            return Ok(());
+        };
+        let Some(path) = source_cache.path_for_id(src_id) else {
+            return Ok(());
+        };
+        let spans = diagnostic.spans();
+        let src = source_cache.source_for_id(src_id).unwrap_or_default();
+        let line_index = source_cache.line_index(src_id);
+        let resolve = |span: &Span| {
+            let start = source_cache.line_col(src_id, span.start(), ColumnUnit::Char)?;
+            let mut end = source_cache.line_col(src_id, span.end(), ColumnUnit::Char)?;
+            // A span ending just after a newline ends on the line with the newline.
+            if end.col == 0 && end.line > start.line {
+                let text = line_index?.line_text(src, end.line - 1)?;
+                end = LineCol {
+                    line: end.line - 1,
+                    col: text.chars().count(),
+                };
+            }
+            Some((start, end))
+        };
+        let resolved = spans.iter().map(resolve).collect::<Vec<_>>();
+        let max_line = resolved
+            .iter()
+            .flatten()
+            .map(|(_, end)| end.line + 1)
+            .max()
+            .unwrap_or(0);
+        let width = max_line.to_string().len();
+        let gutter = |line: Option<usize>| {
+            let number = line.map(|l| (l + 1).to_string()).unwrap_or_default();
+            self.paint(BOLD_BLUE, &format!("{number:>width$} |"))
+        };
+
+        match resolved.first() {
+            Some(Some((start, _))) => writeln!(
+                out,
+                "{:width$}{} {}:{}:{}",
+                "",
+                self.paint(BOLD_BLUE, "-->"),
+                path.display(),
+                start.line + 1,
+                start.col + 1
+            )?,
+            _ => writeln!(
+                out,
+                "{:width$}{} {}",
+                "",
+                self.paint(BOLD_BLUE, "-->"),
+                path.display()
+            )?,
+        }
+        let Some(line_index) = line_index else {
+            return Ok(());
+        };
+        if spans.is_empty() {
+            return Ok(());
+        }
+        writeln!(out, "{}", gutter(None))?;
+        for (idx, (span, resolved)) in spans.iter().zip(&resolved).enumerate() {
+            let Some((start, end)) = resolved else {
+                writeln!(
+                    out,
+                    "{} note: span {}..{} is outside of the source",
+                    gutter(None),
+                    span.start(),
+                    span.end()
+                )?;
+                continue;
+            };
+            let (marker, style) = if idx == 0 {
+                ('^', severity_style)
+            } else {
+                ('-', BOLD_BLUE)
+            };
+            for line in start.line..=end.line {
+                let text = line_index.line_text(src, line).unwrap_or_default();
+                let line_len = text.chars().count();
+                let from = if line == start.line { start.col } else { 0 };
+                let to = if line == end.line { end.col } else { line_len };
+                let (display, from) = expand_tabs(text, from.min(line_len));
+                let (_, to) = expand_tabs(text, to.min(line_len));
+                writeln!(out, "{} {}", gutter(Some(line)), display)?;
+                let underline = marker.to_string().repeat(to.saturating_sub(from).max(1));
+                let mut underline = format!("{:from$}{}", "", self.paint(style, &underline));
+                if line == end.line {
+                    if let Some(label) = diagnostic.format_span(idx) {
+                        underline.push(' ');
+                        underline.push_str(&self.paint(style, &label.to_string()));
+                    }
+                }
+                writeln!(out, "{} {}", gutter(None), underline.trim_end())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders a diagnostic to a `String` without colour.
+    pub fn render_to_string<E: Spanned + SourceArtifact>(
+        severity: Severity,
+        diagnostic: &E,
+        source_cache: &SourceCache,
+    ) -> String {
+        let mut out = Vec::new();
+        Self::new(false)
+            .render(severity, diagnostic, source_cache, &mut out)
+            .expect("Writing to a Vec cannot fail");
+        String::from_utf8(out).expect("Rendered output is UTF-8")
+    }
+}
+
+/// Replaces tabs with 4 spaces, returning the text and `col` adjusted to match.
+fn expand_tabs(text: &str, col: usize) -> (String, usize) {
+    let tabs_before = text.chars().take(col).filter(|&c| c == '\t').count();
+    (text.replace('\t', "    "), col + tabs_before * 3)
+}
+
+/// A `Diagnostics` implementation which renders errors and warnings in the style of rustc.
+///
+/// Diagnostics are buffered in the order they are emitted, and rendered
+/// to the writer when `no_more_data` is called, at which point the
+/// source text the spans refer to is available.
+pub struct TerminalDiagnostics<X: Tool, W: io::Write = io::Stderr> {
+    renderer: Renderer,
+    writer: W,
+    pending: Vec<Diagnostic<X>>,
+    error_count: usize,
+    warning_count: usize,
+}
+
+impl<X: Tool> TerminalDiagnostics<X, io::Stderr> {
+    /// Renders to stderr, with colour decided by `color`.
+    pub fn stderr(color: ColorChoice) -> Self {
+        let stderr = io::stderr();
+        let renderer = Renderer::new(color.should_color(&stderr));
+        Self::new(stderr, renderer)
+    }
+}
+
+impl<X: Tool, W: io::Write> TerminalDiagnostics<X, W> {
+    pub fn new(writer: W, renderer: Renderer) -> Self {
+        Self {
+            renderer,
+            writer,
+            pending: vec![],
+            error_count: 0,
+            warning_count: 0,
+        }
+    }
+    /// The number of errors rendered so far.
+    pub fn error_count(&self) -> usize {
+        self.error_count
+    }
+    /// The number of warnings rendered so far.
+    pub fn warning_count(&self) -> usize {
+        self.warning_count
+    }
+    pub fn writer(&self) -> &W {
+        &self.writer
+    }
+    pub fn into_writer(self) -> W {
+        self.writer
+    }
+}
+
+buffered_diagnostics!(TerminalDiagnostics);
+
+impl<X: Tool, W: io::Write> BufferedDiagnostics<X> for TerminalDiagnostics<X, W> {
+    fn pending(&mut self) -> &mut Vec<Diagnostic<X>> {
+        &mut self.pending
+    }
+    fn write_diagnostics(&mut self, diagnostics: Vec<Diagnostic<X>>, env: &DiagnosticsEnv<'_, X>) {
+        for diagnostic in diagnostics {
+            match diagnostic.severity() {
+                Severity::Error => self.error_count += 1,
+                Severity::Warning => self.warning_count += 1,
+            }
+            let _ = self
+                .renderer
+                .render_diagnostic(&diagnostic, env.source_cache(), &mut self.writer)
+                .and_then(|()| writeln!(self.writer));
+        }
+        let _ = self.writer.flush();
+    }
+}
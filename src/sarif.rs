@@ -0,0 +1,389 @@
+use crate::{
+    code::{lookup_error_code, ErrorCode},
+    diagnostics::{
+        buffered_diagnostics, BufferedDiagnostics, Diagnostic, DiagnosticsEnv, Severity,
+    },
+    json::push_json_string,
+    line_index::{ColumnUnit, LineCol},
+    source::SourceCache,
+    tool::Tool,
+    Span,
+};
+use std::{collections::HashMap, fmt::Write as _, io, path};
+
+/// A [SARIF 2.1.0](https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html) log.
+///
+/// A log contains one run per tool, each of which records the artifacts
+/// (sources) of the sessions it was given, and the results (diagnostics)
+/// referring to them. Runs for the same tool are merged.
+#[derive(Debug, Default, Clone)]
+pub struct SarifLog {
+    runs: Vec<SarifRun>,
+}
+
+#[derive(Debug, Clone)]
+struct SarifRun {
+    tool_name: &'static str,
+    /// The path of each artifact, indexed by their position.
+    artifacts: Vec<path::PathBuf>,
+    artifact_indices: HashMap<path::PathBuf, usize>,
+    results: Vec<SarifResult>,
+    /// The registered codes of the results.
+    rules: Vec<&'static ErrorCode>,
+}
+
+#[derive(Debug, Clone)]
+struct SarifResult {
+    severity: Severity,
+    rule_id: Option<&'static str>,
+    message: String,
+    /// The first location is the primary location, the rest are related locations.
+    locations: Vec<SarifLocation>,
+}
+
+#[derive(Debug, Clone)]
+struct SarifLocation {
+    artifact: usize,
+    span: Span,
+    /// One based start and end line/column pairs.
+    region: Option<(LineCol, LineCol)>,
+    label: Option<String>,
+}
+
+impl SarifLocation {
+    /// Writes the `physicalLocation` and `message` properties of a location object.
+    fn write_json(&self, out: &mut String, path: &path::Path) {
+        out.push_str("\"physicalLocation\":{\"artifactLocation\":{\"uri\":");
+        push_json_string(out, &path_to_uri(path));
+        write!(out, ",\"index\":{}}},\"region\":{{", self.artifact).unwrap();
+        if let Some((start, end)) = self.region {
+            write!(
+                out,
+                "\"startLine\":{},\"startColumn\":{},\"endLine\":{},\"endColumn\":{},",
+                start.line, start.col, end.line, end.col
+            )
+            .unwrap();
+        }
+        write!(
+            out,
+            "\"byteOffset\":{},\"byteLength\":{}}}}}",
+            self.span.start(),
+            self.span.len()
+        )
+        .unwrap();
+        if let Some(label) = &self.label {
+            out.push_str(",\"message\":{\"text\":");
+            push_json_string(out, label);
+            out.push('}');
+        }
+    }
+}
+
+impl SarifRun {
+    /// The index of the artifact for `path`, which is shared by every source loaded from it.
+    fn artifact_index(&mut self, path: &path::Path) -> usize {
+        if let Some(idx) = self.artifact_indices.get(path) {
+            return *idx;
+        }
+        self.artifacts.push(path.to_path_buf());
+        self.artifact_indices
+            .insert(path.to_path_buf(), self.artifacts.len() - 1);
+        self.artifacts.len() - 1
+    }
+
+    fn push_result<X: Tool>(&mut self, diagnostic: &Diagnostic<X>, source_cache: &SourceCache) {
+        if let Some(error_code) = diagnostic.code().and_then(lookup_error_code::<X>) {
+            if !self.rules.contains(&error_code) {
+                self.rules.push(error_code);
+            }
+        }
+        let mut locations = vec![];
+        if let Some(src_id) = diagnostic.source_id() {
+            if let Some(path) = source_cache.path_for_id(src_id) {
+                let artifact = self.artifact_index(path);
+                for (idx, span) in diagnostic.spans().iter().enumerate() {
+                    let line_col = |offset| {
+                        source_cache
+                            .line_col(src_id, offset, ColumnUnit::Char)
+                            .map(|lc| LineCol {
+                                line: lc.line + 1,
+                                col: lc.col + 1,
+                            })
+                    };
+                    let region = line_col(span.start()).zip(line_col(span.end()));
+                    locations.push(SarifLocation {
+                        artifact,
+                        span: *span,
+                        region,
+                        label: diagnostic.span_label(idx),
+                    });
+                }
+            }
+        }
+        self.results.push(SarifResult {
+            severity: diagnostic.severity(),
+            rule_id: diagnostic.code().or(diagnostic.lint_name()),
+            message: diagnostic.to_string(),
+            locations,
+        });
+    }
+
+    fn write_json(&self, out: &mut String) {
+        out.push_str("{\"tool\":{\"driver\":{\"name\":");
+        push_json_string(out, self.tool_name);
+        if !self.rules.is_empty() {
+            out.push_str(",\"rules\":[");
+            for (idx, rule) in self.rules.iter().enumerate() {
+                if idx != 0 {
+                    out.push(',');
+                }
+                out.push_str("{\"id\":");
+                push_json_string(out, rule.code);
+                out.push_str(",\"help\":{\"text\":");
+                push_json_string(out, rule.explanation);
+                out.push_str(",\"markdown\":");
+                push_json_string(out, rule.explanation);
+                out.push_str("}}");
+            }
+            out.push(']');
+        }
+        out.push_str("}},\"columnKind\":\"unicodeCodePoints\",\"artifacts\":[");
+        for (idx, path) in self.artifacts.iter().enumerate() {
+            if idx != 0 {
+                out.push(',');
+            }
+            out.push_str("{\"location\":{\"uri\":");
+            push_json_string(out, &path_to_uri(path));
+            out.push_str("}}");
+        }
+        out.push_str("],\"results\":[");
+        for (idx, result) in self.results.iter().enumerate() {
+            if idx != 0 {
+                out.push(',');
+            }
+            write!(
+                out,
+                "{{\"level\":\"{}\",\"message\":{{\"text\":",
+                result.severity
+            )
+            .unwrap();
+            push_json_string(out, &result.message);
+            out.push('}');
+            if let Some(rule_id) = result.rule_id {
+                out.push_str(",\"ruleId\":");
+                push_json_string(out, rule_id);
+            }
+            let mut locations = result.locations.iter();
+            if let Some(primary) = locations.next() {
+                out.push_str(",\"locations\":[{");
+                primary.write_json(out, &self.artifacts[primary.artifact]);
+                out.push_str("}]");
+            }
+            if result.locations.len() > 1 {
+                out.push_str(",\"relatedLocations\":[");
+                for (idx, related) in locations.enumerate() {
+                    if idx != 0 {
+                        out.push(',');
+                    }
+                    write!(out, "{{\"id\":{},", idx + 1).unwrap();
+                    related.write_json(out, &self.artifacts[related.artifact]);
+                    out.push('}');
+                }
+                out.push(']');
+            }
+            out.push('}');
+        }
+        out.push_str("]}");
+    }
+}
+
+impl SarifLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn run_mut(&mut self, tool_name: &'static str) -> &mut SarifRun {
+        let idx = match self.runs.iter().position(|run| run.tool_name == tool_name) {
+            Some(idx) => idx,
+            None => {
+                self.runs.push(SarifRun {
+                    tool_name,
+                    artifacts: vec![],
+                    artifact_indices: HashMap::new(),
+                    results: vec![],
+                    rules: vec![],
+                });
+                self.runs.len() - 1
+            }
+        };
+        &mut self.runs[idx]
+    }
+
+    /// Records every source of a session as an artifact, and the diagnostics of that session
+    /// in the run for `X`.
+    pub fn add_session<X: Tool>(
+        &mut self,
+        diagnostics: &[Diagnostic<X>],
+        env: &DiagnosticsEnv<'_, X>,
+    ) {
+        let source_cache = env.source_cache();
+        let session = env.session();
+        let run = self.run_mut(X::name());
+        for src_id in session.all_source_ids() {
+            if let Some(path) = source_cache.path_for_id(src_id) {
+                run.artifact_index(path);
+            }
+        }
+        for diagnostic in diagnostics {
+            run.push_result(diagnostic, source_cache);
+        }
+    }
+
+    /// Moves the runs of `other` into `self`, merging runs of the same tool.
+    pub fn append(&mut self, other: SarifLog) {
+        for other_run in other.runs {
+            let run = self.run_mut(other_run.tool_name);
+            for rule in other_run.rules {
+                if !run.rules.contains(&rule) {
+                    run.rules.push(rule);
+                }
+            }
+            let indices = other_run
+                .artifacts
+                .iter()
+                .map(|path| run.artifact_index(path))
+                .collect::<Vec<_>>();
+            // Results refer to artifacts by index, so renumber them.
+            run.results
+                .extend(other_run.results.into_iter().map(|mut result| {
+                    for location in &mut result.locations {
+                        location.artifact = indices[location.artifact];
+                    }
+                    result
+                }));
+        }
+    }
+
+    /// Serializes the log as JSON.
+    pub fn to_json(&self) -> String {
+        let mut out = String::from(
+            "{\"$schema\":\"https://json.schemastore.org/sarif-2.1.0.json\",\"version\":\"2.1.0\",\"runs\":[",
+        );
+        for (idx, run) in self.runs.iter().enumerate() {
+            if idx != 0 {
+                out.push(',');
+            }
+            run.write_json(&mut out);
+        }
+        out.push_str("]}");
+        out
+    }
+
+    pub fn write(&self, out: &mut impl io::Write) -> io::Result<()> {
+        writeln!(out, "{}", self.to_json())
+    }
+}
+
+/// Converts a path to a relative URI reference, percent encoding reserved characters.
+fn path_to_uri(path: &path::Path) -> String {
+    let mut uri = String::new();
+    for (idx, component) in path.components().enumerate() {
+        match component {
+            path::Component::RootDir => {
+                uri.push('/');
+                continue;
+            }
+            path::Component::Prefix(prefix) => {
+                uri.push_str(&prefix.as_os_str().to_string_lossy());
+                continue;
+            }
+            _ => (),
+        }
+        if idx != 0 && !uri.ends_with('/') {
+            uri.push('/');
+        }
+        for b in component.as_os_str().to_string_lossy().bytes() {
+            if b.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@".contains(&b) {
+                uri.push(b as char);
+            } else {
+                write!(uri, "%{b:02X}").unwrap();
+            }
+        }
+    }
+    uri
+}
+
+/// A `Diagnostics` implementation producing a [SarifLog].
+///
+/// Diagnostics are buffered until `no_more_data`, where they are added
+/// to the log along with an artifact for every source in the session.
+///
+/// The sink may be passed through multiple drivers, the runs of each are
+/// accumulated into the one log. If the sink was given a writer, the log is
+/// written to it once, by [finish](Self::finish) or when the sink is dropped.
+/// To combine the logs of several tools, use [SarifDiagnostics::buffered],
+/// and combine and write the logs with [SarifLog::append] and [SarifLog::write].
+pub struct SarifDiagnostics<X: Tool, W: io::Write = io::Stdout> {
+    writer: Option<W>,
+    log: SarifLog,
+    pending: Vec<Diagnostic<X>>,
+}
+
+impl<X: Tool, W: io::Write> SarifDiagnostics<X, W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Some(writer),
+            log: SarifLog::new(),
+            pending: vec![],
+        }
+    }
+    /// A sink which only accumulates the log, without writing it.
+    pub fn buffered() -> Self {
+        Self {
+            writer: None,
+            log: SarifLog::new(),
+            pending: vec![],
+        }
+    }
+    pub fn log(&self) -> &SarifLog {
+        &self.log
+    }
+    /// Takes the log, which is then not written to the writer.
+    pub fn into_log(mut self) -> SarifLog {
+        self.writer = None;
+        std::mem::take(&mut self.log)
+    }
+    pub fn writer(&self) -> Option<&W> {
+        self.writer.as_ref()
+    }
+    /// Writes the log to the writer and flushes it, returning the writer,
+    /// or `None` if the sink is [buffered](Self::buffered).
+    pub fn finish(mut self) -> io::Result<Option<W>> {
+        let mut writer = self.writer.take();
+        if let Some(writer) = &mut writer {
+            self.log.write(writer)?;
+            writer.flush()?;
+        }
+        Ok(writer)
+    }
+}
+
+impl<X: Tool, W: io::Write> Drop for SarifDiagnostics<X, W> {
+    /// Writes the log if [finish](Self::finish) was not called.
+    fn drop(&mut self) {
+        if let Some(writer) = &mut self.writer {
+            let _ = self.log.write(writer).and_then(|()| writer.flush());
+        }
+    }
+}
+
+buffered_diagnostics!(SarifDiagnostics);
+
+impl<X: Tool, W: io::Write> BufferedDiagnostics<X> for SarifDiagnostics<X, W> {
+    fn pending(&mut self) -> &mut Vec<Diagnostic<X>> {
+        &mut self.pending
+    }
+    fn write_diagnostics(&mut self, diagnostics: Vec<Diagnostic<X>>, env: &DiagnosticsEnv<'_, X>) {
+        self.log.add_session(&diagnostics, env);
+    }
+}
@@ -1,15 +1,59 @@
 use std::path;
-use std::{collections::HashMap, sync::atomic::Ordering};
+use std::sync::{Arc, OnceLock};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::atomic::Ordering,
+};
 
-use crate::NEXT_SOURCE_ID;
+use crate::fix::{apply_suggestions, Applicability, FixError, Suggestion};
+use crate::graph::{Dependency, DependencyKind};
+use crate::line_index::{ColumnUnit, LineCol, LineIndex};
+use crate::NEXT_SOURCE_CACHE_ID;
 
-#[derive(PartialEq, Eq, Hash, Copy, Clone)]
+#[derive(PartialEq, Eq, Hash, Copy, Clone, PartialOrd, Ord)]
 /// Opaque Id for source strings
 ///
 /// * A source string may have multiple SourceIDs.
 /// * A SourceId refers uniquely to a single source string.
-#[derive(Debug)]
-pub struct SourceId(pub(crate) usize);
+/// * A SourceId belongs to the `SourceCache` which allocated it, and is
+///   not found in any other `SourceCache`.
+///
+/// Each `SourceCache` allocates ids in sequence from zero, so their position
+/// does not depend on other caches in the process. The `Debug` output shows only
+/// the position, so messages containing it are the same from run to run, ids of
+/// different caches are told apart by [cache_id](Self::cache_id).
+pub struct SourceId {
+    /// The identity of the `SourceCache` which allocated this id.
+    pub(crate) cache: u32,
+    pub(crate) idx: u32,
+}
+
+impl SourceId {
+    /// The identity of the `SourceCache` which allocated this id.
+    ///
+    /// Identities are unique within a process, but depend on how many caches
+    /// were created before, so should not be shown to users or persisted.
+    pub fn cache_id(self) -> u32 {
+        self.cache
+    }
+
+    /// The position of this id within the `SourceCache` which allocated it.
+    ///
+    /// Unlike the identity of the cache this doesn't depend on the rest of the
+    /// process, so it may be written to disk to refer to sources of the same cache.
+    #[cfg(feature = "cache")]
+    pub(crate) fn raw(self) -> u64 {
+        u64::from(self.idx)
+    }
+}
+
+impl fmt::Debug for SourceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SourceId").field(&self.idx).finish()
+    }
+}
 
 /// For obtaining a SourceId from an error.
 pub trait SourceArtifact {
@@ -30,27 +74,207 @@ pub trait SourceArtifact {
 /// present in the source cache is when applying fixes based on error recovery.
 ///
 /// Modifications to a `SourceCache` are tracked in a [Session].
+///
+/// Line and column information is computed on demand, the first
+/// request for a `SourceId` builds a [LineIndex] which is kept for
+/// subsequent lookups.
+///
+/// Sources read by a driver may be deduplicated, see [Dedup].
 pub struct SourceCache {
-    pub(crate) cache: HashMap<SourceId, (std::path::PathBuf, String)>,
+    /// The identity embedded in the `SourceId`s this cache allocates.
+    id: u32,
+    next_idx: u32,
+    dedup: Dedup,
+    /// A source with each loaded path and content hash, when deduplicating.
+    loaded: HashMap<(path::PathBuf, u64), SourceId>,
+    pub(crate) cache: BTreeMap<SourceId, CachedSource>,
+}
+
+/// Whether a [SourceCache] shares sources with the same path and text.
+///
+/// Only sources read by a driver, or included by a tool, are deduplicated.
+/// Generated and fixed sources always get their own `SourceId` and text.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Dedup {
+    /// Every source is stored separately.
+    #[default]
+    Off,
+    /// Each load gets a new `SourceId`, sharing the text of an identical source.
+    Buffers,
+    /// Each load of an identical source returns the same `SourceId`.
+    ///
+    /// A `Session` still records that it loaded the source, so the same `SourceId`
+    /// may be found in the sessions of several drivers.
+    SourceIds,
+}
+
+pub(crate) struct CachedSource {
+    pub(crate) path: path::PathBuf,
+    pub(crate) src: Arc<str>,
+    line_index: OnceLock<LineIndex>,
+    /// The number of sessions which loaded this source.
+    refs: usize,
+}
+
+impl CachedSource {
+    pub(crate) fn new(path: path::PathBuf, src: Arc<str>) -> Self {
+        Self {
+            path,
+            src,
+            line_index: OnceLock::new(),
+            refs: 1,
+        }
+    }
 }
 
 impl SourceCache {
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
+        let id = NEXT_SOURCE_CACHE_ID.fetch_add(1, Ordering::SeqCst);
         Self {
-            cache: HashMap::new(),
+            id: u32::try_from(id).expect("too many source caches"),
+            next_idx: 0,
+            dedup: Dedup::Off,
+            loaded: HashMap::new(),
+            cache: BTreeMap::new(),
         }
     }
+
+    /// Sets how sources loaded into the cache are deduplicated, defaults to [Dedup::Off].
+    pub fn with_dedup(mut self, dedup: Dedup) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// The number of bytes of source text stored, counting shared text once.
+    pub fn source_bytes(&self) -> usize {
+        let mut seen = HashSet::new();
+        self.cache
+            .values()
+            .filter(|entry| seen.insert(Arc::as_ptr(&entry.src)))
+            .map(|entry| entry.src.len())
+            .sum()
+    }
+
+    /// Every `SourceId` in the cache, in the order they were allocated.
     pub fn source_ids(&self) -> impl Iterator<Item = SourceId> + '_ {
         self.cache.keys().copied()
     }
 
     pub fn source_for_id(&self, src_id: SourceId) -> Option<&str> {
-        self.cache.get(&src_id).map(|(_, src)| src.as_str())
+        self.cache.get(&src_id).map(|entry| &*entry.src)
     }
 
     pub fn path_for_id(&self, src_id: SourceId) -> Option<&path::Path> {
-        self.cache.get(&src_id).map(|(path, _)| path.as_path())
+        self.cache.get(&src_id).map(|entry| entry.path.as_path())
+    }
+
+    /// Returns the line index for `src_id`, building it if necessary.
+    pub fn line_index(&self, src_id: SourceId) -> Option<&LineIndex> {
+        self.cache
+            .get(&src_id)
+            .map(|entry| entry.line_index.get_or_init(|| LineIndex::new(&entry.src)))
+    }
+
+    /// Converts a byte offset within the source of `src_id` to a line and column.
+    pub fn line_col(&self, src_id: SourceId, offset: usize, unit: ColumnUnit) -> Option<LineCol> {
+        let src = self.source_for_id(src_id)?;
+        self.line_index(src_id)?.line_col(src, offset, unit)
+    }
+
+    /// Converts a line and column within the source of `src_id` to a byte offset.
+    pub fn offset(&self, src_id: SourceId, line_col: LineCol, unit: ColumnUnit) -> Option<usize> {
+        let src = self.source_for_id(src_id)?;
+        self.line_index(src_id)?.offset(src, line_col, unit)
+    }
+
+    fn allocate(&mut self) -> SourceId {
+        let src_id = SourceId {
+            cache: self.id,
+            idx: self.next_idx,
+        };
+        self.next_idx = self.next_idx.checked_add(1).expect("too many sources");
+        src_id
+    }
+
+    /// Inserts source text under a newly allocated `SourceId`.
+    pub(crate) fn insert(&mut self, path: path::PathBuf, src: String) -> SourceId {
+        let src_id = self.allocate();
+        self.cache
+            .insert(src_id, CachedSource::new(path, src.into()));
+        src_id
+    }
+
+    /// Inserts source text read by a driver or included by a tool,
+    /// deduplicating it according to the cache's [Dedup].
+    pub(crate) fn load(&mut self, path: path::PathBuf, src: String) -> SourceId {
+        if self.dedup == Dedup::Off {
+            return self.insert(path, src);
+        }
+        let mut hasher = DefaultHasher::new();
+        src.hash(&mut hasher);
+        let key = (path, hasher.finish());
+        let existing = self
+            .loaded
+            .get(&key)
+            .and_then(|src_id| Some((*src_id, self.cache.get_mut(src_id)?)))
+            .filter(|(_, entry)| *entry.src == *src);
+        let src = match existing {
+            Some((src_id, entry)) if self.dedup == Dedup::SourceIds => {
+                entry.refs += 1;
+                return src_id;
+            }
+            Some((_, entry)) => entry.src.clone(),
+            None => src.into(),
+        };
+        let src_id = self.allocate();
+        self.cache
+            .insert(src_id, CachedSource::new(key.0.clone(), src));
+        self.loaded.insert(key, src_id);
+        src_id
+    }
+
+    /// Adds a reference to `src_id`, for a session which uses it without loading it again.
+    pub(crate) fn retain(&mut self, src_id: SourceId) {
+        if let Some(entry) = self.cache.get_mut(&src_id) {
+            entry.refs += 1;
+        }
+    }
+
+    /// Drops a reference to `src_id`, removing it once no session refers to it.
+    ///
+    /// Call this for each of a session's `loaded_source_ids` once the session
+    /// is no longer used, so sources shared through `Dedup::SourceIds` are freed.
+    pub fn release(&mut self, src_id: SourceId) {
+        let Some(entry) = self.cache.get_mut(&src_id) else {
+            return;
+        };
+        entry.refs -= 1;
+        if entry.refs == 0 {
+            self.cache.remove(&src_id);
+            self.loaded.retain(|_, loaded_id| *loaded_id != src_id);
+        }
+    }
+
+    /// Moves every source of `other` into this cache under newly allocated `SourceId`s,
+    /// in the order `other` allocated them. The moved sources are not deduplicated,
+    /// but sources loaded later are deduplicated against those `other` loaded.
+    ///
+    /// Returns the new `SourceId` of each source of `other`, which may be applied to
+    /// sessions with [Session::remap_source_ids].
+    pub fn merge(&mut self, other: SourceCache) -> HashMap<SourceId, SourceId> {
+        let mut mapping = HashMap::with_capacity(other.cache.len());
+        for (src_id, source) in other.cache {
+            let new_id = self.allocate();
+            self.cache.insert(new_id, source);
+            mapping.insert(src_id, new_id);
+        }
+        for (key, src_id) in other.loaded {
+            if let Some(new_id) = mapping.get(&src_id) {
+                self.loaded.entry(key).or_insert(*new_id);
+            }
+        }
+        mapping
     }
 
     /// This should allow us to populate the source cache with generated code.
@@ -61,8 +285,7 @@ impl SourceCache {
         src: String,
         kind: SourceKind,
     ) -> SourceId {
-        let source_id = SourceId(NEXT_SOURCE_ID.fetch_add(1, Ordering::SeqCst));
-        self.cache.insert(source_id, (path, src));
+        let source_id = self.insert(path, src);
         session.add_source_id(source_id, kind);
         source_id
     }
@@ -74,10 +297,31 @@ impl SourceCache {
 ///
 /// Whenever `Driver` or a tool loads source text
 /// into a `SourceCache`, they track their changes here.
+#[derive(Clone)]
 pub struct Session<SourceKind> {
     pub(crate) source_ids_from_driver: Vec<SourceId>,
     pub(crate) source_ids_from_tool: Vec<SourceId>,
     pub(crate) source_kinds: HashMap<SourceId, SourceKind>,
+    pub(crate) source_ids_from_fixes: Vec<SourceId>,
+    pub(crate) suggestions: HashMap<SourceId, Vec<Suggestion>>,
+    pub(crate) source_ids_from_includes: Vec<SourceId>,
+    pub(crate) dependencies: Vec<Dependency>,
+    pub(crate) file_dependencies: Vec<path::PathBuf>,
+}
+
+impl<SourceKind> Default for Session<SourceKind> {
+    fn default() -> Self {
+        Self {
+            source_ids_from_driver: vec![],
+            source_ids_from_tool: vec![],
+            source_kinds: HashMap::new(),
+            source_ids_from_fixes: vec![],
+            suggestions: HashMap::new(),
+            source_ids_from_includes: vec![],
+            dependencies: vec![],
+            file_dependencies: vec![],
+        }
+    }
 }
 
 impl<SourceKind> Session<SourceKind> {
@@ -87,7 +331,105 @@ impl<SourceKind> Session<SourceKind> {
     }
     /// Any new source id's produced by the tool through `SourceCache::add_source`.
     pub fn added_source_ids(&self) -> &[SourceId] {
-        &self.source_ids_from_driver
+        &self.source_ids_from_tool
+    }
+    /// Any new source id's produced by [apply_fixes](Self::apply_fixes).
+    pub fn fixed_source_ids(&self) -> &[SourceId] {
+        &self.source_ids_from_fixes
+    }
+    /// The `SourceId` that `src_id` was produced from by applying fixes.
+    pub fn fixed_from(&self, src_id: SourceId) -> Option<SourceId> {
+        self.dependencies(src_id)
+            .find(|edge| edge.kind == DependencyKind::Fixed)
+            .map(|edge| edge.dependency)
+    }
+    /// Every source loaded, included, added or fixed in this session.
+    pub(crate) fn all_source_ids(&self) -> impl Iterator<Item = SourceId> + '_ {
+        self.source_ids_from_driver
+            .iter()
+            .chain(&self.source_ids_from_includes)
+            .chain(&self.source_ids_from_tool)
+            .chain(&self.source_ids_from_fixes)
+            .copied()
+    }
+    /// Any new source id's loaded by the tool through `ToolInitEnv::include`.
+    pub fn included_source_ids(&self) -> &[SourceId] {
+        &self.source_ids_from_includes
+    }
+    /// The `SourceId` which included `src_id`.
+    pub fn included_from(&self, src_id: SourceId) -> Option<SourceId> {
+        self.dependents(src_id)
+            .find(|edge| edge.kind == DependencyKind::Include)
+            .map(|edge| edge.dependent)
+    }
+    /// The files read by the driver and includes, in the order they were first read.
+    ///
+    /// Sources which were not read from a file, such as named strings, standard
+    /// input, and generated sources are excluded. See [SourceProvider::file_path](crate::SourceProvider::file_path).
+    pub fn file_dependencies(&self) -> &[path::PathBuf] {
+        &self.file_dependencies
+    }
+    pub(crate) fn add_file_dependency(&mut self, path: Option<path::PathBuf>) {
+        if let Some(path) = path.filter(|path| !self.file_dependencies.contains(path)) {
+            self.file_dependencies.push(path);
+        }
+    }
+    /// Suggestions of all applicabilities from the diagnostics emitted for `src_id`.
+    pub fn suggestions(&self, src_id: SourceId) -> &[Suggestion] {
+        self.suggestions.get(&src_id).map_or(&[], Vec::as_slice)
+    }
+    /// Applies the machine applicable suggestions for `src_id`.
+    ///
+    /// The fixed text is added to `source_cache` under a new `SourceId` with the same
+    /// path, which is recorded in this session as fixed from `src_id`. Returns `None`
+    /// if there were no machine applicable suggestions. If any suggestions conflict
+    /// no fixes are applied and the conflicts are returned.
+    pub fn apply_fixes(
+        &mut self,
+        source_cache: &mut SourceCache,
+        src_id: SourceId,
+    ) -> Result<Option<SourceId>, FixError> {
+        let fixes = self
+            .suggestions(src_id)
+            .iter()
+            .filter(|s| s.applicability == Applicability::MachineApplicable)
+            .collect::<Vec<_>>();
+        if fixes.is_empty() {
+            return Ok(None);
+        }
+        let src = source_cache
+            .source_for_id(src_id)
+            .ok_or(FixError::UnknownSource(src_id))?;
+        let fixed = apply_suggestions(src, fixes)?;
+        let path = source_cache.path_for_id(src_id).unwrap().to_path_buf();
+        let fixed_id = source_cache.insert(path, fixed);
+        self.source_ids_from_fixes.push(fixed_id);
+        self.add_dependency(fixed_id, src_id, DependencyKind::Fixed);
+        Ok(Some(fixed_id))
+    }
+    /// Replaces every `SourceId` in the session which is a key of `mapping`,
+    /// such as the mapping returned by [SourceCache::merge].
+    pub fn remap_source_ids(&mut self, mapping: &HashMap<SourceId, SourceId>) {
+        let remapped = |src_id| mapping.get(&src_id).copied().unwrap_or(src_id);
+        let remap = |src_id: &mut SourceId| *src_id = remapped(*src_id);
+        self.source_ids_from_driver
+            .iter_mut()
+            .chain(&mut self.source_ids_from_tool)
+            .chain(&mut self.source_ids_from_fixes)
+            .chain(&mut self.source_ids_from_includes)
+            .for_each(remap);
+        for edge in &mut self.dependencies {
+            remap(&mut edge.dependent);
+            remap(&mut edge.dependency);
+        }
+        self.source_kinds = std::mem::take(&mut self.source_kinds)
+            .into_iter()
+            .map(|(src_id, kind)| (remapped(src_id), kind))
+            .collect();
+        self.suggestions = std::mem::take(&mut self.suggestions)
+            .into_iter()
+            .map(|(src_id, suggestions)| (remapped(src_id), suggestions))
+            .collect();
     }
     pub(crate) fn add_source_id(&mut self, src_id: SourceId, kind: SourceKind) {
         self.source_ids_from_tool.push(src_id);
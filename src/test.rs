@@ -1,7 +1,7 @@
 use crate::default_impls::DefaultDriverEnv;
 use crate::default_impls::SimpleDiagnostics;
 use dir_view::DirView;
-use std::{collections::HashMap, fmt, path};
+use std::{fmt, path};
 #[cfg(test)]
 mod tests {
     #![allow(dead_code)]
@@ -18,6 +18,13 @@ mod tests {
     }
 
     impl Tool for Yacc {
+        fn name() -> &'static str {
+            "yacc"
+        }
+        const ERROR_CODES: &'static [ErrorCode] = &[ErrorCode {
+            code: "Y0001",
+            explanation: "A rule was defined more than once.\n\n```\nfoo: 'a';\nfoo: 'b';\n```\n",
+        }];
         type Error = YaccGrammarError;
         type Warning = YaccGrammarWarning;
         type Output = GrammarASTWithValidationCertificate;
@@ -91,32 +98,40 @@ mod tests {
                 YaccGrammarErrorKind::Testing(_) => YaccGrammarSpansKind::Duplicate,
             }
         }
-        fn format_span(self, idx: usize) -> Option<impl fmt::Display> {
-            if idx == 0 {
-                return None;
-            }
+        fn format_span(&self, idx: usize) -> Option<impl fmt::Display> {
             match self.spans_kind {
+                YaccGrammarSpansKind::Duplicate if idx == 0 => None,
                 YaccGrammarSpansKind::Duplicate => Some("Duplicate"),
+                YaccGrammarSpansKind::Location => Some("here"),
+            }
+        }
+        fn code(&self) -> Option<&'static str> {
+            match self.spans_kind {
+                YaccGrammarSpansKind::Duplicate => Some("Y0001"),
                 YaccGrammarSpansKind::Location => None,
             }
         }
     }
     impl fmt::Display for YaccGrammarError {
-        fn fmt(&self, _fmt: &mut fmt::Formatter) -> fmt::Result {
-            unimplemented!()
+        fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+            match self.spans_kind {
+                YaccGrammarSpansKind::Duplicate => write!(fmt, "Duplicate rule"),
+                YaccGrammarSpansKind::Location => write!(fmt, "Yacc error test"),
+            }
         }
     }
 
     impl Error for YaccGrammarError {}
     impl fmt::Display for YaccGrammarWarning {
-        fn fmt(&self, _fmt: &mut fmt::Formatter) -> fmt::Result {
-            unimplemented!()
+        fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+            write!(fmt, "Yacc warning test")
         }
     }
 
     struct YaccGrammarWarning {
         source_id: Option<SourceId>,
         kind: YaccGrammarWarningKind,
+        suggestions: Vec<Suggestion>,
     }
     impl SourceArtifact for YaccGrammarWarning {
         fn source_id(&self) -> Option<SourceId> {
@@ -139,9 +154,17 @@ mod tests {
                 YaccGrammarWarningKind::Testing(_) => YaccGrammarSpansKind::Duplicate,
             }
         }
-        fn format_span(self, _idx: usize) -> Option<impl fmt::Display> {
+        fn format_span(&self, _idx: usize) -> Option<impl fmt::Display> {
             None::<&'_ str>
         }
+        fn lint_name(&self) -> &'static str {
+            match self.kind {
+                YaccGrammarWarningKind::Testing(_) => "testing",
+            }
+        }
+        fn suggestions(&self) -> &[Suggestion] {
+            &self.suggestions
+        }
     }
 
     #[derive(Default)]
@@ -177,7 +200,7 @@ mod tests {
             let source_id = tool_env.session.loaded_source_ids().first();
             if let Some(source_id) = source_id.copied() {
                 if let Some(path) = tool_env.source_cache.path_for_id(source_id) {
-                    if path == path::PathBuf::from("Cargo.toml") {
+                    if path == path::Path::new("Cargo.toml") {
                         tool_env.emitter.emit_non_fatal_error(YaccGrammarError {
                             source_id: Some(source_id),
                             kind: YaccGrammarErrorKind::Testing(vec![]),
@@ -274,58 +297,55 @@ mod tests {
         }
     }
 
-    #[test]
-    fn unit_driver() {
-        impl _unstable_api_::InternalTrait for () {}
-        impl DriverSelector for () {}
-        impl Args for () {
-            type RequiredArgs = ();
-            type OptionalArgs = bool;
-        }
+    impl _unstable_api_::InternalTrait for () {}
+    impl DriverSelector for () {}
+    impl Args for () {
+        type RequiredArgs = ();
+        type OptionalArgs = bool;
+    }
 
-        impl<X: Tool> DriverTypes<X> for () {
-            type Output<T> = () where T: Tool;
-            type DriverEnv<'a, T, D> = () where T: Tool + 'a, D: Diagnostics<T> + 'a;
+    impl<X: Tool> DriverTypes<X> for () {
+        type Output<T> = () where T: Tool;
+        type DriverEnv<'a, T, D> = () where T: Tool + 'a, D: Diagnostics<T> + 'a;
+    }
+    // Note that the args here differ from those of the default `driver_init`.
+    // Not for any reason, just to highlight that there can be multiple impls
+    // for this struct due to the default type instace. The other being:
+    //
+    // impl<X, ...> Driver<... , DefaultDriver>.
+    //
+    // With this we can change both the `driver_init` implementation,
+    // and the `DriverArgs`, or just change `driver_init`.
+    impl<X> Driver<X, ()>
+    where
+        X: Tool,
+        (<() as Args>::RequiredArgs, <() as Args>::OptionalArgs): Into<Params<()>>,
+        (X::RequiredArgs, X::OptionalArgs): Into<Params<X>>,
+    {
+        pub fn driver_init<D: Diagnostics<X>>(
+            self,
+            driver_env: DefaultDriverEnv<'_, X, D>,
+            _extra_param: (),
+        ) -> Result<X::Output, DriverError> {
+            let _driver_args: Params<()> = self.driver_args.into();
+            let emitter = DiagnosticsEmitter::new(self.tool, driver_env.diagnostics);
+            let mut source_cache = SourceCache::new();
+            let session: Session<X::SourceKind> = Session::default();
+            let mut tool_env = ToolInitEnv {
+                source_cache: &mut source_cache,
+                emitter,
+                session,
+                include_provider: None,
+            };
+            Ok(X::Output::tool_init(self.tool_args.into(), &mut tool_env))
         }
+    }
+
+    #[test]
+    fn unit_driver() {
         // These fields should perhaps be combined into something?
         let mut diagnostics = SimpleDiagnostics::default();
         let mut source_cache = SourceCache::new();
-        // Note that the args here differ from those of the default `driver_init`.
-        // Not for any reason, just to highlight that there can be multiple impls
-        // for this struct due to the default type instace. The other being:
-        //
-        // impl<X, ...> Driver<... , DefaultDriver>.
-        //
-        // With this we can change both the `driver_init` implementation,
-        // and the `DriverArgs`, or just change `driver_init`.
-        impl<X> Driver<X, ()>
-        where
-            X: Tool,
-            (<() as Args>::RequiredArgs, <() as Args>::OptionalArgs): Into<Params<()>>,
-            (X::RequiredArgs, X::OptionalArgs): Into<Params<X>>,
-        {
-            pub fn driver_init<D: Diagnostics<X>>(
-                self,
-                driver_env: DefaultDriverEnv<'_, X, D>,
-                _extra_param: (),
-            ) -> Result<X::Output, DriverError> {
-                let _driver_args: Params<()> = self.driver_args.into();
-                let emitter = DiagnosticsEmitter::new(self.tool, driver_env.diagnostics);
-                let mut source_cache = SourceCache::new();
-                let session: Session<X::SourceKind> = Session {
-                    source_ids_from_driver: vec![],
-                    source_ids_from_tool: vec![],
-                    source_kinds: HashMap::new(),
-                };
-                let mut tool_env = ToolInitEnv {
-                    source_cache: &mut source_cache,
-                    emitter,
-                    session,
-                };
-                Ok(X::Output::tool_init(self.tool_args.into(), &mut tool_env))
-            }
-        }
-
         {
             let driver_env = DefaultDriverEnv {
                 source_cache: &mut source_cache,
@@ -355,6 +375,7 @@ mod tests {
 
     #[derive(Copy, Clone)]
     struct Lex;
+    #[derive(Clone)]
     struct NeverWarnings(Option<std::convert::Infallible>);
     impl fmt::Display for NeverWarnings {
         fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
@@ -362,12 +383,12 @@ mod tests {
         }
     }
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     enum LexErrorKind {
         Testing(Vec<Span>),
     }
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
 
     struct LexError {
         source_id: Option<SourceId>,
@@ -402,7 +423,7 @@ mod tests {
                 LexErrorKind::Testing(_) => Self::SpansKind::Location,
             }
         }
-        fn format_span(self, _idx: usize) -> Option<impl fmt::Display> {
+        fn format_span(&self, _idx: usize) -> Option<impl fmt::Display> {
             None::<&'_ str>
         }
     }
@@ -416,7 +437,7 @@ mod tests {
         fn spanskind(&self) -> Self::SpansKind {
             unreachable!()
         }
-        fn format_span(self, _idx: usize) -> Option<impl fmt::Display> {
+        fn format_span(&self, _idx: usize) -> Option<impl fmt::Display> {
             None::<&'_ str>
         }
     }
@@ -438,12 +459,16 @@ mod tests {
         }
     }
 
+    #[derive(Clone)]
     enum LexSourceKind {
         LexSourceInput,
         LexRustSourceOutput,
     }
 
     impl Tool for Lex {
+        fn name() -> &'static str {
+            "lex"
+        }
         type Error = LexError;
         type Warning = NeverWarnings;
         // FIXME look at what lex returns.
@@ -542,4 +567,1835 @@ mod tests {
         }
         assert_eq!(source_cache.source_ids().collect::<Vec<_>>().len(), 2);
     }
+
+    #[test]
+    fn dedup_shared_sources() {
+        let shared = || {
+            let memory = MemorySources::new().with("shared.y", GRAMMAR);
+            vec![ProvidedSources::new(memory).with("shared.y")]
+        };
+        let load_yacc = |source_cache: &mut SourceCache| {
+            let mut diagnostics = SimpleDiagnostics::default();
+            let driver = Driver {
+                tool: Yacc,
+                driver: DefaultDriver,
+                driver_args: (
+                    DefaultDriverArgs {},
+                    DefaultDriverOptionalArgs {
+                        provided_sources: shared(),
+                        ..Default::default()
+                    },
+                ),
+                tool_args: (
+                    YaccArgs {
+                        yacc_kind: YaccKind::Grmtools,
+                    },
+                    Default::default(),
+                ),
+            }
+            .driver_init(DefaultDriverEnv {
+                source_cache,
+                diagnostics: &mut diagnostics,
+                tool: Yacc,
+            })
+            .unwrap();
+            driver.session.loaded_source_ids().to_vec()
+        };
+        let load_lex = |source_cache: &mut SourceCache| {
+            let mut diagnostics = SimpleDiagnostics::default();
+            let driver = Driver {
+                tool: Lex,
+                driver: DefaultDriver,
+                driver_args: (
+                    DefaultDriverArgs {},
+                    DefaultDriverOptionalArgs {
+                        provided_sources: shared(),
+                        ..Default::default()
+                    },
+                ),
+                tool_args: ((), ()),
+            }
+            .driver_init(DefaultDriverEnv {
+                source_cache,
+                diagnostics: &mut diagnostics,
+                tool: Lex,
+            })
+            .unwrap();
+            driver.session.loaded_source_ids().to_vec()
+        };
+
+        let mut source_cache = SourceCache::new();
+        load_yacc(&mut source_cache);
+        load_lex(&mut source_cache);
+        assert_eq!(source_cache.source_bytes(), 2 * GRAMMAR.len());
+
+        let mut source_cache = SourceCache::new().with_dedup(Dedup::Buffers);
+        let yacc_ids = load_yacc(&mut source_cache);
+        let lex_ids = load_lex(&mut source_cache);
+        assert_ne!(yacc_ids, lex_ids);
+        assert_eq!(source_cache.source_ids().count(), 2);
+        assert_eq!(source_cache.source_bytes(), GRAMMAR.len());
+
+        let mut source_cache = SourceCache::new().with_dedup(Dedup::SourceIds);
+        let yacc_ids = load_yacc(&mut source_cache);
+        let lex_ids = load_lex(&mut source_cache);
+        assert_eq!(yacc_ids, lex_ids);
+        assert_eq!(source_cache.source_ids().count(), 1);
+        assert_eq!(source_cache.source_bytes(), GRAMMAR.len());
+        let src_id = yacc_ids[0];
+        source_cache.release(src_id);
+        assert!(source_cache.source_for_id(src_id).is_some());
+        source_cache.release(src_id);
+        assert_eq!(source_cache.source_for_id(src_id), None);
+        assert_ne!(load_lex(&mut source_cache), [src_id]);
+    }
+
+    #[test]
+    fn line_col_units() {
+        let src = "a\u{e9}\u{1F600}b\r\nsecond\n";
+        let mut source_cache = SourceCache::new();
+        let mut session: Session<YaccSourceKind> = Session::default();
+        let src_id = source_cache.add_source(
+            &mut session,
+            "unicode.y".into(),
+            src.to_string(),
+            YaccSourceKind::YaccSourceInput,
+        );
+        let b = src.find('b').unwrap();
+        let lc = |unit| source_cache.line_col(src_id, b, unit).unwrap();
+        assert_eq!(lc(ColumnUnit::Byte), LineCol { line: 0, col: 7 });
+        assert_eq!(lc(ColumnUnit::Char), LineCol { line: 0, col: 3 });
+        assert_eq!(lc(ColumnUnit::Utf16), LineCol { line: 0, col: 4 });
+        for unit in [ColumnUnit::Byte, ColumnUnit::Char, ColumnUnit::Utf16] {
+            assert_eq!(source_cache.offset(src_id, lc(unit), unit), Some(b));
+        }
+        // Inside the multi-byte e acute, and inside the surrogate pair.
+        assert_eq!(source_cache.line_col(src_id, 2, ColumnUnit::Char), None);
+        let mid_surrogate = LineCol { line: 0, col: 3 };
+        assert_eq!(
+            source_cache.offset(src_id, mid_surrogate, ColumnUnit::Utf16),
+            None
+        );
+
+        let second = src.find("second").unwrap();
+        assert_eq!(
+            source_cache.line_col(src_id, second + 2, ColumnUnit::Char),
+            Some(LineCol { line: 1, col: 2 })
+        );
+        // The `\r` is part of the line terminator, so it can't be addressed by column.
+        let past_cr = LineCol { line: 0, col: 5 };
+        assert_eq!(source_cache.offset(src_id, past_cr, ColumnUnit::Char), None);
+        let end_of_line = LineCol { line: 0, col: 4 };
+        assert_eq!(
+            source_cache.offset(src_id, end_of_line, ColumnUnit::Char),
+            Some(src.find('\r').unwrap())
+        );
+        // Offsets on either char of the `\r\n` are at the end of the line.
+        let cr = src.find('\r').unwrap();
+        for offset in [cr, cr + 1] {
+            for unit in [ColumnUnit::Byte, ColumnUnit::Char, ColumnUnit::Utf16] {
+                let lc = source_cache.line_col(src_id, offset, unit).unwrap();
+                assert_eq!(lc.line, 0);
+                assert_eq!(source_cache.offset(src_id, lc, unit), Some(cr));
+            }
+        }
+        assert_eq!(
+            source_cache.line_col(src_id, cr + 1, ColumnUnit::Char),
+            Some(end_of_line)
+        );
+
+        let line_index = source_cache.line_index(src_id).unwrap();
+        assert_eq!(line_index.line_count(), 3);
+        assert_eq!(line_index.line_text(src, 1), Some("second"));
+        assert_eq!(line_index.line_text(src, 2), Some(""));
+        assert_eq!(
+            source_cache.line_col(src_id, src.len(), ColumnUnit::Byte),
+            Some(LineCol { line: 2, col: 0 })
+        );
+        assert_eq!(
+            source_cache.line_col(src_id, src.len() + 1, ColumnUnit::Byte),
+            None
+        );
+    }
+
+    fn duplicate_rule_error(source_id: SourceId, spans: Vec<Span>) -> YaccGrammarError {
+        YaccGrammarError {
+            source_id: Some(source_id),
+            kind: YaccGrammarErrorKind::Testing(spans),
+            spans_kind: YaccGrammarSpansKind::Duplicate,
+        }
+    }
+
+    const GRAMMAR: &str = "foo: 'a';\nbar: 'b';\nfoo: 'c';\n";
+
+    fn grammar_source(source_cache: &mut SourceCache) -> (Session<YaccSourceKind>, SourceId) {
+        let mut session = Session::default();
+        let src_id = source_cache.add_source(
+            &mut session,
+            "grammar.y".into(),
+            GRAMMAR.to_string(),
+            YaccSourceKind::YaccSourceInput,
+        );
+        (session, src_id)
+    }
+
+    #[test]
+    fn render_duplicate_rule() {
+        let mut source_cache = SourceCache::new();
+        let (_session, src_id) = grammar_source(&mut source_cache);
+        let e = duplicate_rule_error(src_id, vec![Span::new(20, 23), Span::new(0, 3)]);
+        let rendered = Renderer::render_to_string(Severity::Error, &e, &source_cache);
+        assert_eq!(
+            rendered,
+            "error[Y0001]: Duplicate rule\n \
+             --> grammar.y:3:1\n  \
+              |\n\
+             3 | foo: 'c';\n  \
+              | ^^^\n\
+             1 | foo: 'a';\n  \
+              | --- Duplicate\n"
+        );
+    }
+
+    #[test]
+    fn render_multiline_and_out_of_range() {
+        let mut source_cache = SourceCache::new();
+        let (_session, src_id) = grammar_source(&mut source_cache);
+        let e = duplicate_rule_error(src_id, vec![Span::new(5, 13), Span::new(100, 101)]);
+        let rendered = Renderer::render_to_string(Severity::Error, &e, &source_cache);
+        assert_eq!(
+            rendered,
+            "error[Y0001]: Duplicate rule\n \
+             --> grammar.y:1:6\n  \
+              |\n\
+             1 | foo: 'a';\n  \
+              |      ^^^^\n\
+             2 | bar: 'b';\n  \
+              | ^^^\n  \
+              | note: span 100..101 is outside of the source\n"
+        );
+        // Ending just after the newline does not underline the following line.
+        let e = duplicate_rule_error(src_id, vec![Span::new(0, 10)]);
+        let rendered = Renderer::render_to_string(Severity::Error, &e, &source_cache);
+        assert_eq!(
+            rendered,
+            "error[Y0001]: Duplicate rule\n \
+             --> grammar.y:1:1\n  \
+              |\n\
+             1 | foo: 'a';\n  \
+              | ^^^^^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn render_crlf_line_endings() {
+        let mut source_cache = SourceCache::new();
+        let mut session: Session<YaccSourceKind> = Session::default();
+        let src_id = source_cache.add_source(
+            &mut session,
+            "crlf.y".into(),
+            "foo: 'a'\r\nbar: 'b';\r\n".to_string(),
+            YaccSourceKind::YaccSourceInput,
+        );
+        // Spans starting on the `\n` of a `\r\n` start at the end of the line.
+        let e = duplicate_rule_error(src_id, vec![Span::new(9, 10)]);
+        let rendered = Renderer::render_to_string(Severity::Error, &e, &source_cache);
+        assert_eq!(
+            rendered,
+            "error[Y0001]: Duplicate rule\n \
+             --> crlf.y:1:9\n  \
+              |\n\
+             1 | foo: 'a'\n  \
+              |         ^\n"
+        );
+        let e = duplicate_rule_error(src_id, vec![Span::new(9, 21)]);
+        let rendered = Renderer::render_to_string(Severity::Error, &e, &source_cache);
+        assert_eq!(
+            rendered,
+            "error[Y0001]: Duplicate rule\n \
+             --> crlf.y:1:9\n  \
+              |\n\
+             1 | foo: 'a'\n  \
+              |         ^\n\
+             2 | bar: 'b';\n  \
+              | ^^^^^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn terminal_diagnostics_render_on_no_more_data() {
+        let mut source_cache = SourceCache::new();
+        let (session, src_id) = grammar_source(&mut source_cache);
+        let mut diagnostics: TerminalDiagnostics<Yacc, Vec<u8>> =
+            TerminalDiagnostics::new(Vec::new(), Renderer::new(true));
+        diagnostics.emit_error(duplicate_rule_error(src_id, vec![Span::new(0, 3)]));
+        diagnostics.emit_warning(YaccGrammarWarning {
+            source_id: None,
+            kind: YaccGrammarWarningKind::Testing(vec![]),
+            suggestions: vec![],
+        });
+        assert!(diagnostics.writer().is_empty());
+        diagnostics.no_more_data(&DiagnosticsEnv::new(&source_cache, &session));
+        assert_eq!(diagnostics.error_count(), 1);
+        assert_eq!(diagnostics.warning_count(), 1);
+        let out = String::from_utf8(diagnostics.into_writer()).unwrap();
+        assert!(out.starts_with("\x1b[1;31merror[Y0001]\x1b[0m"));
+        assert!(out.contains("\x1b[1;33mwarning\x1b[0m\x1b[1m: Yacc warning test"));
+        assert!(!ColorChoice::Never.should_color(&std::io::stderr()));
+    }
+
+    #[test]
+    fn json_diagnostics_lines() {
+        let mut source_cache = SourceCache::new();
+        let (session, src_id) = grammar_source(&mut source_cache);
+        let mut diagnostics: JsonDiagnostics<Yacc, Vec<u8>> = JsonDiagnostics::new(Vec::new());
+        diagnostics.emit_error(duplicate_rule_error(
+            src_id,
+            vec![Span::new(20, 23), Span::new(0, 3)],
+        ));
+        diagnostics.emit_warning(YaccGrammarWarning {
+            source_id: None,
+            kind: YaccGrammarWarningKind::Testing(vec![Span::new(1, 2)]),
+            suggestions: vec![],
+        });
+        diagnostics.no_more_data(&DiagnosticsEnv::new(&source_cache, &session));
+        let out = String::from_utf8(diagnostics.into_writer()).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "{\"version\":1,\"severity\":\"error\",\"message\":\"Duplicate rule\",\"code\":\"Y0001\",\"lint\":null,\"path\":\"grammar.y\",\"spans\":[\
+                 {\"start\":20,\"end\":23,\"line_start\":3,\"column_start\":1,\"line_end\":3,\"column_end\":4,\"label\":null},\
+                 {\"start\":0,\"end\":3,\"line_start\":1,\"column_start\":1,\"line_end\":1,\"column_end\":4,\"label\":\"Duplicate\"}]}",
+                "{\"version\":1,\"severity\":\"warning\",\"message\":\"Yacc warning test\",\"code\":null,\"lint\":\"testing\",\"path\":null,\"spans\":[\
+                 {\"start\":1,\"end\":2,\"line_start\":null,\"column_start\":null,\"line_end\":null,\"column_end\":null,\"label\":null}]}",
+            ]
+        );
+        let mut escaped = String::new();
+        push_json_string(&mut escaped, "a \"quoted\"\\path\n\u{1}");
+        assert_eq!(escaped, "\"a \\\"quoted\\\"\\\\path\\n\\u0001\"");
+    }
+
+    /// Checks `value` against the JSON schema subset used by `testdata/sarif-2.1.0-shape.json`.
+    fn check_shape(
+        value: &serde_json::Value,
+        shape: &serde_json::Value,
+        root: &serde_json::Value,
+        at: &str,
+    ) {
+        use serde_json::Value;
+        if let Some(Value::String(reference)) = shape.get("$ref") {
+            let name = reference.trim_start_matches("#/definitions/");
+            return check_shape(value, &root["definitions"][name], root, at);
+        }
+        if let Some(ty) = shape.get("type").and_then(Value::as_str) {
+            let ok = match ty {
+                "object" => value.is_object(),
+                "array" => value.is_array(),
+                "string" => value.is_string(),
+                "integer" => value.is_i64() || value.is_u64(),
+                _ => panic!("unsupported type {ty}"),
+            };
+            assert!(ok, "{at}: expected {ty}, found {value}");
+        }
+        if let Some(Value::Array(variants)) = shape.get("enum") {
+            assert!(variants.contains(value), "{at}: {value} not in {variants:?}");
+        }
+        if let Some(minimum) = shape.get("minimum").and_then(Value::as_i64) {
+            assert!(value.as_i64().unwrap() >= minimum, "{at}: {value} < {minimum}");
+        }
+        if let Some(Value::Array(required)) = shape.get("required") {
+            for property in required {
+                let property = property.as_str().unwrap();
+                assert!(value.get(property).is_some(), "{at}: missing {property}");
+            }
+        }
+        if let Some(Value::Object(properties)) = shape.get("properties") {
+            for (property, property_value) in value.as_object().unwrap() {
+                let property_shape = properties
+                    .get(property)
+                    .unwrap_or_else(|| panic!("{at}: unexpected property {property}"));
+                check_shape(property_value, property_shape, root, &format!("{at}.{property}"));
+            }
+        }
+        if let Some(items) = shape.get("items") {
+            for (idx, item) in value.as_array().unwrap().iter().enumerate() {
+                check_shape(item, items, root, &format!("{at}[{idx}]"));
+            }
+        }
+    }
+
+    #[test]
+    fn sarif_log_matches_schema_shape() {
+        let shape: serde_json::Value =
+            serde_json::from_str(include_str!("testdata/sarif-2.1.0-shape.json")).unwrap();
+        let mut source_cache = SourceCache::new();
+        let (mut session, src_id) = grammar_source(&mut source_cache);
+        source_cache.add_source(
+            &mut session,
+            "out dir/grammar.rs".into(),
+            String::new(),
+            YaccSourceKind::YaccRustSourceOutput,
+        );
+        let mut diagnostics: SarifDiagnostics<Yacc, Vec<u8>> = SarifDiagnostics::new(Vec::new());
+        diagnostics.emit_error(duplicate_rule_error(
+            src_id,
+            vec![Span::new(20, 23), Span::new(0, 3)],
+        ));
+        diagnostics.emit_warning(YaccGrammarWarning {
+            source_id: None,
+            kind: YaccGrammarWarningKind::Testing(vec![]),
+            suggestions: vec![],
+        });
+        diagnostics.no_more_data(&DiagnosticsEnv::new(&source_cache, &session));
+        // A second driver run loading the same file shares its artifact.
+        let (mut session, src_id) = grammar_source(&mut source_cache);
+        diagnostics.emit_error(YaccGrammarError {
+            source_id: Some(src_id),
+            kind: YaccGrammarErrorKind::Testing(vec![Span::new(0, 3)]),
+            spans_kind: YaccGrammarSpansKind::Location,
+        });
+        // Included sources get an artifact, even without results pointing at them.
+        let included = source_cache.load("tokens.y".into(), "%token A\n".into());
+        session.source_ids_from_includes.push(included);
+        diagnostics.no_more_data(&DiagnosticsEnv::new(&source_cache, &session));
+        assert!(diagnostics.writer().unwrap().is_empty());
+        let mut combined = diagnostics.log().clone();
+        let written = diagnostics.finish().unwrap().unwrap();
+
+        let log: serde_json::Value = serde_json::from_slice(&written).unwrap();
+        check_shape(&log, &shape, &shape, "$");
+        let run = &log["runs"][0];
+        assert_eq!(run["tool"]["driver"]["name"], "yacc");
+        assert_eq!(run["artifacts"].as_array().unwrap().len(), 3);
+        assert_eq!(run["artifacts"][1]["location"]["uri"], "out%20dir/grammar.rs");
+        assert_eq!(run["artifacts"][2]["location"]["uri"], "tokens.y");
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        let primary = &results[2]["locations"][0];
+        assert_eq!(primary["physicalLocation"]["artifactLocation"]["index"], 0);
+        assert_eq!(primary["message"]["text"], "here");
+        let region = &results[0]["locations"][0]["physicalLocation"]["region"];
+        assert_eq!(region["startLine"], 3);
+        assert_eq!(region["endColumn"], 4);
+        let related = &results[0]["relatedLocations"][0];
+        assert_eq!(related["message"]["text"], "Duplicate");
+        assert_eq!(results[0]["ruleId"], "Y0001");
+        assert_eq!(results[1]["ruleId"], "testing");
+        assert_eq!(run["tool"]["driver"]["rules"][0]["id"], "Y0001");
+        assert_eq!(related["physicalLocation"]["artifactLocation"]["index"], 0);
+        assert!(results[1].get("locations").is_none());
+
+        // A second tool gets its own run, with artifact indices renumbered when merging.
+        let mut lex_session: Session<LexSourceKind> = Session::default();
+        let lex_src = source_cache.add_source(
+            &mut lex_session,
+            "lexer.l".into(),
+            "%%\n".into(),
+            LexSourceKind::LexSourceInput,
+        );
+        let mut lex_diagnostics: SarifDiagnostics<Lex, Vec<u8>> = SarifDiagnostics::buffered();
+        lex_diagnostics.emit_error(LexError {
+            source_id: Some(lex_src),
+            kind: LexErrorKind::Testing(vec![Span::new(0, 2)]),
+        });
+        lex_diagnostics.no_more_data(&DiagnosticsEnv::new(&source_cache, &lex_session));
+        combined.append(lex_diagnostics.into_log());
+        let log: serde_json::Value = serde_json::from_str(&combined.to_json()).unwrap();
+        check_shape(&log, &shape, &shape, "$");
+        assert_eq!(log["runs"].as_array().unwrap().len(), 2);
+        assert_eq!(log["runs"][1]["tool"]["driver"]["name"], "lex");
+    }
+
+    #[test]
+    fn quickfix_output() {
+        let mut source_cache = SourceCache::new();
+        let (_session, src_id) = grammar_source(&mut source_cache);
+        let e = duplicate_rule_error(src_id, vec![Span::new(20, 23), Span::new(0, 3)]);
+        assert_eq!(
+            quickfix_lines(Severity::Error, &e, &source_cache),
+            [
+                "grammar.y:3:1: error[Y0001]: Duplicate rule",
+                "grammar.y:1:1: error[Y0001]: Duplicate rule (Duplicate)"
+            ]
+        );
+
+        // Named strings are reported under their given name.
+        let mut diagnostics: QuickfixDiagnostics<Yacc, Vec<u8>> =
+            QuickfixDiagnostics::new(Vec::new());
+        let driver = Driver {
+            tool: Yacc,
+            driver: DefaultDriver,
+            driver_args: (
+                DefaultDriverArgs {},
+                DefaultDriverOptionalArgs {
+                    named_string: Some(("Cargo.toml".into(), "[package]\n".into())),
+                    ..Default::default()
+                },
+            ),
+            tool_args: (
+                YaccArgs {
+                    yacc_kind: YaccKind::Grmtools,
+                },
+                Default::default(),
+            ),
+        }
+        .driver_init(DefaultDriverEnv {
+            source_cache: &mut source_cache,
+            diagnostics: &mut diagnostics,
+            tool: Yacc,
+        })
+        .unwrap();
+        assert!(driver.output.grammar().is_err());
+        assert_eq!(
+            String::from_utf8(diagnostics.into_writer()).unwrap(),
+            "Cargo.toml: error: Yacc error test\n"
+        );
+    }
+
+    #[test]
+    fn lint_levels() {
+        let mut levels = LintLevels::new()
+            .with("testing", LintLevel::Forbid)
+            .with("other", LintLevel::Allow);
+        assert!(!levels.set("testing", LintLevel::Allow));
+        assert_eq!(levels.level("testing"), LintLevel::Forbid);
+        assert_eq!(levels.level("unconfigured"), LintLevel::Warn);
+        let levels = levels.deny_warnings(true);
+        assert_eq!(levels.level("unconfigured"), LintLevel::Deny);
+        assert_eq!(levels.level("other"), LintLevel::Allow);
+        // Diagnostics without their own lint names, codes or tool names use the defaults.
+        let lex_error = LexError {
+            source_id: None,
+            kind: LexErrorKind::Testing(vec![]),
+        };
+        assert_eq!(lex_error.lint_name(), "LexError");
+        assert_eq!(lex_error.code(), None);
+        assert!(lex_error.suggestions().is_empty());
+        assert_eq!(Counted::name(), "Counted");
+
+        let source_cache = SourceCache::new();
+        let session = Session::default();
+        let warning = || YaccGrammarWarning {
+            source_id: None,
+            kind: YaccGrammarWarningKind::Testing(vec![]),
+            suggestions: vec![],
+        };
+        let mut diagnostics: QuickfixDiagnostics<Yacc, Vec<u8>> =
+            QuickfixDiagnostics::new(Vec::new());
+        for (level, observed_warning, observed_error, output) in [
+            (LintLevel::Allow, false, false, ""),
+            (LintLevel::Warn, true, false, "warning: Yacc warning test\n"),
+            (LintLevel::Deny, false, true, "error: Yacc warning test\n"),
+        ] {
+            let mut emitter = DiagnosticsEmitter::new(Yacc, &mut diagnostics)
+                .with_lint_levels(LintLevels::new().with("testing", level));
+            emitter.emit_warning(warning());
+            assert_eq!(emitter.observed_warning(), observed_warning);
+            assert_eq!(emitter.observed_error(), observed_error);
+            emitter.no_more_data(&DiagnosticsEnv::new(&source_cache, &session));
+            drop(emitter);
+            assert_eq!(String::from_utf8_lossy(diagnostics.writer()), output);
+            diagnostics = QuickfixDiagnostics::new(Vec::new());
+        }
+    }
+
+    #[test]
+    fn explain_error_code() {
+        let explanation = Driver::<Yacc>::explain("y0001").unwrap();
+        assert!(explanation.starts_with("A rule was defined more than once."));
+        assert_eq!(Driver::<Yacc>::explain("Y9999"), None);
+        assert_eq!(Driver::<Lex>::explain("Y0001"), None);
+    }
+
+    fn replace(start: usize, end: usize, replacement: &str) -> Suggestion {
+        Suggestion {
+            span: Span::new(start, end),
+            replacement: replacement.to_string(),
+            applicability: Applicability::MachineApplicable,
+        }
+    }
+
+    #[test]
+    fn apply_machine_applicable_fixes() {
+        let mut source_cache = SourceCache::new();
+        let (session, src_id) = grammar_source(&mut source_cache);
+        let mut diagnostics: SimpleDiagnostics<Yacc> = SimpleDiagnostics::default();
+        let mut tool_env = ToolInitEnv {
+            emitter: DiagnosticsEmitter::new(Yacc, &mut diagnostics),
+            source_cache: &mut source_cache,
+            session,
+            include_provider: None,
+        };
+        let mut maybe_incorrect = replace(0, 0, "// ");
+        maybe_incorrect.applicability = Applicability::MaybeIncorrect;
+        tool_env.emitter.emit_warning(YaccGrammarWarning {
+            source_id: Some(src_id),
+            kind: YaccGrammarWarningKind::Testing(vec![Span::new(20, 23)]),
+            suggestions: vec![replace(20, 23, "baz"), maybe_incorrect],
+        });
+        tool_env.emitter.emit_warning(YaccGrammarWarning {
+            source_id: Some(src_id),
+            kind: YaccGrammarWarningKind::Testing(vec![Span::new(15, 18)]),
+            suggestions: vec![replace(15, 18, "'B'"), replace(20, 23, "baz")],
+        });
+        let mut session = tool_env.finish();
+        assert_eq!(session.suggestions(src_id).len(), 4);
+
+        let fixed_id = session
+            .apply_fixes(&mut source_cache, src_id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            source_cache.source_for_id(fixed_id),
+            Some("foo: 'a';\nbar: 'B';\nbaz: 'c';\n")
+        );
+        assert_eq!(source_cache.source_for_id(src_id), Some(GRAMMAR));
+        assert_eq!(
+            source_cache.path_for_id(fixed_id),
+            source_cache.path_for_id(src_id)
+        );
+        assert_eq!(session.fixed_from(fixed_id), Some(src_id));
+        assert_eq!(session.fixed_source_ids(), [fixed_id]);
+        // Nothing was suggested for the fixed source.
+        assert_eq!(session.apply_fixes(&mut source_cache, fixed_id), Ok(None));
+    }
+
+    #[test]
+    fn conflicting_fixes() {
+        let overlapping = [replace(0, 3, "bar"), replace(2, 5, "x")];
+        assert_eq!(
+            apply_suggestions(GRAMMAR, &overlapping),
+            Err(FixError::Conflicts(vec![(Span::new(0, 3), Span::new(2, 5))]))
+        );
+        let same_insertion = [replace(4, 4, " x"), replace(4, 4, " y")];
+        assert_eq!(
+            apply_suggestions(GRAMMAR, &same_insertion),
+            Err(FixError::Conflicts(vec![(Span::new(4, 4), Span::new(4, 4))]))
+        );
+        let nested = [replace(0, 9, ""), replace(1, 2, "x"), replace(5, 6, "y")];
+        assert_eq!(
+            apply_suggestions(GRAMMAR, &nested),
+            Err(FixError::Conflicts(vec![
+                (Span::new(0, 9), Span::new(1, 2)),
+                (Span::new(0, 9), Span::new(5, 6))
+            ]))
+        );
+        let touching = [replace(0, 3, "x"), replace(3, 3, "y"), replace(3, 4, "z")];
+        assert_eq!(
+            apply_suggestions(GRAMMAR, &touching),
+            Err(FixError::Conflicts(vec![(Span::new(3, 3), Span::new(3, 4))]))
+        );
+        let adjacent = [replace(3, 4, "="), replace(0, 3, "x"), replace(9, 9, " // x")];
+        assert_eq!(
+            apply_suggestions(GRAMMAR, &adjacent).unwrap(),
+            "x= 'a'; // x\nbar: 'b';\nfoo: 'c';\n"
+        );
+        assert_eq!(
+            apply_suggestions(GRAMMAR, &[replace(0, 100, "")]),
+            Err(FixError::InvalidSpan(Span::new(0, 100)))
+        );
+    }
+
+    #[test]
+    fn unified_diff_hunks() {
+        let old = (1..=12).map(|n| format!("{n}\n")).collect::<String>();
+        let new = old
+            .lines()
+            .map(|line| match line {
+                "2" => "two\n".to_string(),
+                "11" => "11\n11.5\n".to_string(),
+                line => format!("{line}\n"),
+            })
+            .collect::<String>();
+        assert_eq!(
+            unified_diff("a/n.txt", &old, "b/n.txt", &new),
+            "--- a/n.txt\n+++ b/n.txt\n\
+             @@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n\
+             @@ -9,4 +9,5 @@\n 9\n 10\n 11\n+11.5\n 12\n"
+        );
+        assert_eq!(
+            unified_diff("a/x", "x\ny", "b/x", "x\ny\n"),
+            "--- a/x\n+++ b/x\n@@ -1,2 +1,2 @@\n x\n-y\n\\ No newline at end of file\n+y\n"
+        );
+        assert_eq!(
+            unified_diff("/dev/null", "", "b/x", "x\n"),
+            "--- /dev/null\n+++ b/x\n@@ -0,0 +1,1 @@\n+x\n"
+        );
+        assert_eq!(unified_diff("a/x", GRAMMAR, "b/x", GRAMMAR), "");
+    }
+
+    #[test]
+    fn unified_diff_large_rewrite() {
+        let old = (0..2000).map(|n| format!("old {n}\n")).collect::<String>();
+        let new = (0..1500).map(|n| format!("new {n}\n")).collect::<String>();
+        let diff = unified_diff("a/x", &old, "b/x", &new);
+        let mut lines = diff.lines();
+        assert_eq!(lines.next(), Some("--- a/x"));
+        assert_eq!(lines.next(), Some("+++ b/x"));
+        assert_eq!(lines.next(), Some("@@ -1,2000 +1,1500 @@"));
+        let rest = lines.collect::<Vec<_>>();
+        assert_eq!(rest.iter().filter(|l| l.starts_with("-old ")).count(), 2000);
+        assert_eq!(rest.iter().filter(|l| l.starts_with("+new ")).count(), 1500);
+        assert_eq!(rest.len(), 3500);
+
+        let mut edited = old.clone();
+        edited.replace_range(0..5, "first");
+        edited.push_str("last\n");
+        assert_eq!(
+            unified_diff("a/x", &old, "b/x", &edited),
+            "--- a/x\n+++ b/x\n\
+             @@ -1,4 +1,4 @@\n-old 0\n+first\n old 1\n old 2\n old 3\n\
+             @@ -1998,3 +1998,4 @@\n old 1997\n old 1998\n old 1999\n+last\n"
+        );
+    }
+
+    #[test]
+    fn session_diffs() {
+        let mut source_cache = SourceCache::new();
+        let (mut session, src_id) = grammar_source(&mut source_cache);
+        session
+            .suggestions
+            .insert(src_id, vec![replace(20, 23, "baz")]);
+        let fixed_id = session
+            .apply_fixes(&mut source_cache, src_id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            source_diff(&source_cache, src_id, fixed_id).unwrap(),
+            "--- a/grammar.y\n+++ b/grammar.y\n\
+             @@ -1,3 +1,3 @@\n foo: 'a';\n bar: 'b';\n-foo: 'c';\n+baz: 'c';\n"
+        );
+        source_cache.add_source(
+            &mut session,
+            "generated.rs".into(),
+            "fn main() {}\n".to_string(),
+            YaccSourceKind::YaccRustSourceOutput,
+        );
+        let dir = cwd_dir_view().unwrap();
+        let mut out = vec![];
+        let differing = write_session_diffs(
+            &session,
+            &source_cache,
+            &dir,
+            |kind| matches!(kind, YaccSourceKind::YaccRustSourceOutput),
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(differing, 2);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.ends_with(
+            "--- /dev/null\n+++ b/generated.rs\n@@ -0,0 +1,1 @@\n+fn main() {}\n"
+        ));
+
+        let output_args = OutputArgs::new(dir, OutputMode::Diff(Box::new(std::io::sink())), |_: &()| true);
+        assert!(matches!(
+            output_args.selected(&session),
+            Err(DriverError::SourceKindMismatch)
+        ));
+    }
+
+    #[test]
+    fn check_mode_reports_stale_sources() {
+        let mut source_cache = SourceCache::new();
+        let mut session: Session<YaccSourceKind> = Session::default();
+        let dir = cwd_dir_view().unwrap();
+        source_cache.add_source(
+            &mut session,
+            "Cargo.toml".into(),
+            dir.read_to_string("Cargo.toml").unwrap(),
+            YaccSourceKind::YaccRustSourceOutput,
+        );
+        let mut check = OutputArgs::new(dir, OutputMode::Check, |kind: &YaccSourceKind| {
+            matches!(kind, YaccSourceKind::YaccRustSourceOutput)
+        });
+        check.run(&session, &source_cache).unwrap();
+
+        source_cache.add_source(
+            &mut session,
+            "generated.rs".into(),
+            "fn main() {}\n".to_string(),
+            YaccSourceKind::YaccRustSourceOutput,
+        );
+        source_cache.add_source(
+            &mut session,
+            "grammar.y".into(),
+            GRAMMAR.to_string(),
+            YaccSourceKind::YaccSourceInput,
+        );
+        match check.run(&session, &source_cache) {
+            Err(DriverError::StaleSources(stale)) => {
+                assert_eq!(stale, [path::PathBuf::from("generated.rs")])
+            }
+            _ => panic!("expected stale sources"),
+        }
+        assert!(!path::Path::new("generated.rs").exists());
+    }
+
+    /// A fresh writable directory under the system temp dir.
+    fn scratch_dir(name: &str) -> (path::PathBuf, DirView) {
+        let path = std::env::temp_dir().join(format!("driver-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        let dir = DirView::open_ambient_dir(
+            &path,
+            dir_view::ViewKind::Full,
+            cap_std::ambient_authority(),
+        )
+        .unwrap();
+        (path, dir)
+    }
+
+    #[test]
+    fn write_mode_writes_changed_sources() {
+        let (scratch, dir) = scratch_dir("write-mode");
+        let mut source_cache = SourceCache::new();
+        let mut session: Session<YaccSourceKind> = Session::default();
+        source_cache.add_source(
+            &mut session,
+            "src/generated.rs".into(),
+            "fn main() {}\n".to_string(),
+            YaccSourceKind::YaccRustSourceOutput,
+        );
+        source_cache.add_source(
+            &mut session,
+            "grammar.y".into(),
+            GRAMMAR.to_string(),
+            YaccSourceKind::YaccSourceInput,
+        );
+        let mut write = OutputArgs::new(dir, OutputMode::Write, |kind: &YaccSourceKind| {
+            matches!(kind, YaccSourceKind::YaccRustSourceOutput)
+        });
+        write.run(&session, &source_cache).unwrap();
+        let generated = scratch.join("src/generated.rs");
+        assert_eq!(std::fs::read_to_string(&generated).unwrap(), "fn main() {}\n");
+        assert!(!scratch.join("grammar.y").exists());
+        assert_eq!(std::fs::read_dir(scratch.join("src")).unwrap().count(), 1);
+
+        // Unchanged contents are not rewritten.
+        let mtime = std::fs::metadata(&generated).unwrap().modified().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        write.run(&session, &source_cache).unwrap();
+        assert_eq!(
+            std::fs::metadata(&generated).unwrap().modified().unwrap(),
+            mtime
+        );
+        assert!(matches!(
+            write_if_changed(&write.dir, path::Path::new("src/generated.rs/x"), ""),
+            Err(DriverError::Write { path, .. }) if path == path::Path::new("src/generated.rs/x")
+        ));
+
+        // A file which isn't UTF-8 is replaced rather than failing to be read.
+        std::fs::write(&generated, b"\xff\xfe").unwrap();
+        assert!(write_if_changed(&write.dir, path::Path::new("src/generated.rs"), "x").unwrap());
+        assert_eq!(std::fs::read_to_string(&generated).unwrap(), "x");
+
+        // Concurrent writes of the same path use distinct temporary files.
+        std::thread::scope(|scope| {
+            for n in 0..8 {
+                let dir = &write.dir;
+                scope.spawn(move || {
+                    for _ in 0..20 {
+                        write_atomic(dir, path::Path::new("src/generated.rs"), &[b'0' + n])
+                            .unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(std::fs::read(&generated).unwrap().len(), 1);
+        assert_eq!(std::fs::read_dir(scratch.join("src")).unwrap().count(), 1);
+        std::fs::remove_dir_all(scratch).unwrap();
+    }
+
+    /// Runs `Yacc` through the default driver with `SimpleDiagnostics`.
+    fn run_yacc(
+        source_cache: &mut SourceCache,
+        optional: DefaultDriverOptionalArgs,
+    ) -> Result<DriverOutput<Yacc>, DriverError> {
+        let mut diagnostics: SimpleDiagnostics<Yacc> = SimpleDiagnostics::default();
+        Driver {
+            driver: DefaultDriver,
+            tool: Yacc,
+            driver_args: (DefaultDriverArgs {}, optional),
+            tool_args: (
+                YaccArgs {
+                    yacc_kind: YaccKind::Grmtools,
+                },
+                Default::default(),
+            ),
+        }
+        .driver_init(DefaultDriverEnv {
+            source_cache,
+            diagnostics: &mut diagnostics,
+            tool: Yacc,
+        })
+    }
+
+    #[test]
+    fn read_sources_from_globs() {
+        let (scratch, dir) = scratch_dir("read-sources");
+        for path in [
+            "grammars/b.y",
+            "grammars/a.y",
+            "grammars/notes.txt",
+            "grammars/nested/c.y",
+            "grammars/nested/skip.y",
+            "grammars/.hidden/d.y",
+            "grammars/target/e.y",
+            "top.y",
+        ] {
+            dir.create_dir_all(path::Path::new(path).parent().unwrap())
+                .unwrap();
+            dir.write(path, format!("// {path}\n")).unwrap();
+        }
+        dir.write(".ignore", "# generated\nskip.y\ntarget/\n!grammars/target/e.y\n")
+            .unwrap();
+        let inputs = InputSources::new(dir)
+            .with("top.y")
+            .with("grammars/**/*.y")
+            .with("grammars/[ab].y")
+            .ignore_file(".ignore");
+        let expected = [
+            "grammars/a.y",
+            "grammars/b.y",
+            "grammars/nested/c.y",
+            "top.y",
+        ]
+        .map(path::PathBuf::from);
+        assert_eq!(inputs.resolve().unwrap(), expected);
+
+        let mut source_cache = SourceCache::new();
+        let driver = run_yacc(
+            &mut source_cache,
+            DefaultDriverOptionalArgs {
+                read_sources: vec![inputs],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let loaded = driver
+            .session
+            .loaded_source_ids()
+            .iter()
+            .map(|src_id| source_cache.path_for_id(*src_id).unwrap().to_path_buf())
+            .collect::<Vec<_>>();
+        assert_eq!(loaded, expected);
+        assert_eq!(
+            source_cache.source_for_id(driver.session.loaded_source_ids()[3]),
+            Some("// top.y\n")
+        );
+        std::fs::remove_dir_all(scratch).unwrap();
+    }
+
+    #[test]
+    fn trailing_any_dirs_glob() {
+        let (scratch, dir) = scratch_dir("trailing-any-dirs");
+        for path in [
+            "grammars/a.y",
+            "grammars/nested/deeper/b.l",
+            "grammars/.hidden",
+            "other/c.y",
+        ] {
+            dir.create_dir_all(path::Path::new(path).parent().unwrap())
+                .unwrap();
+            dir.write(path, "").unwrap();
+        }
+        let inputs = InputSources::new(dir).with("grammars/**");
+        assert_eq!(
+            inputs.resolve().unwrap(),
+            ["grammars/a.y", "grammars/nested/deeper/b.l"].map(path::PathBuf::from)
+        );
+        std::fs::remove_dir_all(scratch).unwrap();
+
+        assert!(wildcard_match("*.y", "grammar.y"));
+        assert!(wildcard_match("g*[!x]?.y", "grammar.y"));
+        assert!(wildcard_match("*a*b*", "xaxxbx"));
+        assert!(!wildcard_match("*a*b*c", "xaxxbx"));
+        assert!(wildcard_match("[a", "[a"));
+        // Would take exponential time if every `*` backtracked independently.
+        let name = "a".repeat(64);
+        assert!(!wildcard_match(&format!("{}b", "*a".repeat(16)), &name));
+    }
+
+    #[test]
+    fn read_stdin() {
+        let mut source_cache = SourceCache::new();
+        let driver = run_yacc(
+            &mut source_cache,
+            DefaultDriverOptionalArgs {
+                read_stdin: Some(StdinSource::from_reader(GRAMMAR.as_bytes())),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let [src_id] = driver.session.loaded_source_ids() else {
+            panic!("expected a single source");
+        };
+        assert_eq!(
+            source_cache.path_for_id(*src_id),
+            Some(path::Path::new("<stdin>"))
+        );
+        assert_eq!(source_cache.source_for_id(*src_id), Some(GRAMMAR));
+
+        let named = StdinSource::from_reader(GRAMMAR.as_bytes()).name("buffer.y");
+        assert_eq!(named.name, path::Path::new("buffer.y"));
+        let invalid = StdinSource::from_reader(&b"foo: '\xff';"[..]);
+        match run_yacc(
+            &mut source_cache,
+            DefaultDriverOptionalArgs {
+                read_stdin: Some(invalid),
+                ..Default::default()
+            },
+        ) {
+            Err(DriverError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+            _ => panic!("expected invalid UTF-8 to be an error"),
+        }
+    }
+
+    #[test]
+    fn memory_source_provider() {
+        let memory = MemorySources::new()
+            .with("b.y", GRAMMAR)
+            .with("a.y", "a: 'a';\n");
+        assert_eq!(
+            memory.paths().collect::<Vec<_>>(),
+            [path::Path::new("a.y"), path::Path::new("b.y")]
+        );
+        let mut source_cache = SourceCache::new();
+        let driver = run_yacc(
+            &mut source_cache,
+            DefaultDriverOptionalArgs {
+                provided_sources: vec![ProvidedSources::new(memory.clone())
+                    .with("b.y")
+                    .with("a.y")],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let sources = driver
+            .session
+            .loaded_source_ids()
+            .iter()
+            .map(|src_id| {
+                (
+                    source_cache.path_for_id(*src_id).unwrap(),
+                    source_cache.source_for_id(*src_id).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sources,
+            [
+                (path::Path::new("b.y"), GRAMMAR),
+                (path::Path::new("a.y"), "a: 'a';\n")
+            ]
+        );
+
+        match run_yacc(
+            &mut source_cache,
+            DefaultDriverOptionalArgs {
+                provided_sources: vec![ProvidedSources::new(memory).with("c.y")],
+                ..Default::default()
+            },
+        ) {
+            Err(DriverError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+            _ => panic!("expected a missing source to be an error"),
+        }
+    }
+
+    #[cfg(feature = "archive")]
+    fn tar_bytes(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (path, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            // Write the name directly, as `set_path` refuses `..`.
+            header.as_gnu_mut().unwrap().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, contents.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn archive_sources() {
+        use std::io::Write as _;
+        let entries = [("./src/grammar.y", GRAMMAR), ("README", "bundle\n")];
+        let tar = tar_bytes(&entries);
+        let mut gz = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gz.write_all(&tar).unwrap();
+        let tar_gz = gz.finish().unwrap();
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        for (path, contents) in entries {
+            zip.start_file(path.trim_start_matches("./"), zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        let zip = zip.finish().unwrap().into_inner();
+
+        for (name, bytes) in [("bundle.tar", tar), ("bundle.tgz", tar_gz), ("bundle.zip", zip)] {
+            let format = ArchiveFormat::from_path(path::Path::new(name)).unwrap();
+            let archive = ArchiveSources::from_bytes(name, &bytes, format).unwrap();
+            assert_eq!(
+                archive.entries().collect::<Vec<_>>(),
+                [path::Path::new("README"), path::Path::new("src/grammar.y")]
+            );
+            let mut source_cache = SourceCache::new();
+            let driver = run_yacc(
+                &mut source_cache,
+                DefaultDriverOptionalArgs {
+                    provided_sources: vec![
+                        archive.select(|entry| entry.extension().is_some_and(|ext| ext == "y"))
+                    ],
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let [src_id] = driver.session.loaded_source_ids() else {
+                panic!("expected a single source");
+            };
+            assert_eq!(
+                source_cache.path_for_id(*src_id).unwrap(),
+                path::PathBuf::from(format!("{name}!/src/grammar.y"))
+            );
+            assert_eq!(source_cache.source_for_id(*src_id), Some(GRAMMAR));
+        }
+
+        let evil = tar_bytes(&[("src/grammar.y", GRAMMAR), ("src/../../evil.y", "")]);
+        match ArchiveSources::from_bytes("evil.tar", &evil, ArchiveFormat::Tar) {
+            Err(e @ ArchiveError::PathTraversal { .. }) => assert_eq!(
+                e.to_string(),
+                "Archive evil.tar contains the entry src/../../evil.y outside of the archive"
+            ),
+            r => panic!("expected the entry to be rejected, got {r:?}"),
+        }
+        let bundle = tar_bytes(&[("src/grammar.y", GRAMMAR)]);
+        let archive =
+            ArchiveSources::from_bytes("bundle.tar", &bundle, ArchiveFormat::Tar).unwrap();
+        // An archive in memory has no file for the driver to depend on.
+        assert_eq!(archive.file_path(path::Path::new("bundle.tar!/src/grammar.y")), None);
+
+        let (scratch, dir) = scratch_dir("archive");
+        dir.write("bundle.tar", &bundle).unwrap();
+        let archive = ArchiveSources::open(&dir, "bundle.tar").unwrap();
+        assert_eq!(
+            archive.file_path(path::Path::new("bundle.tar!/src/grammar.y")),
+            Some(path::PathBuf::from("bundle.tar"))
+        );
+        match ArchiveSources::open_with_limit(&dir, "bundle.tar", ArchiveFormat::Tar, 1024) {
+            Err(e @ ArchiveError::TooLarge { .. }) => assert_eq!(
+                e.to_string(),
+                "Archive bundle.tar is larger than the limit of 1024 bytes"
+            ),
+            r => panic!("expected the archive to be too large, got {r:?}"),
+        }
+        std::fs::remove_dir_all(scratch).unwrap();
+        // The limit also applies to the unpacked files, which may be larger than the archive.
+        let limit = GRAMMAR.len() as u64 - 1;
+        assert!(matches!(
+            ArchiveSources::from_bytes_with_limit("bundle.tar", &bundle, ArchiveFormat::Tar, limit),
+            Err(ArchiveError::TooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn overlay_shadows_files_on_disk() {
+        let (scratch, dir) = scratch_dir("overlay");
+        dir.write("grammar.y", "stale: 'x';\n").unwrap();
+        dir.write("other.y", "other: 'o';\n").unwrap();
+        let overlay = MemorySources::new()
+            .with("grammar.y", GRAMMAR)
+            .with("unsaved.y", "new: 'n';\n");
+        let mut source_cache = SourceCache::new();
+        let driver = run_yacc(
+            &mut source_cache,
+            DefaultDriverOptionalArgs {
+                read_sources: vec![InputSources::new(dir.try_clone().unwrap()).with("*.y")],
+                overlay: overlay.clone(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let sources = driver
+            .session
+            .loaded_source_ids()
+            .iter()
+            .map(|src_id| {
+                (
+                    source_cache.path_for_id(*src_id).unwrap(),
+                    source_cache.source_for_id(*src_id).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sources,
+            [
+                (path::Path::new("grammar.y"), GRAMMAR),
+                (path::Path::new("other.y"), "other: 'o';\n")
+            ]
+        );
+
+        let mut layered = Overlay::new(overlay, dir);
+        assert_eq!(
+            layered.read_source(path::Path::new("unsaved.y")).unwrap(),
+            "new: 'n';\n"
+        );
+        assert_eq!(
+            layered.read_source(path::Path::new("other.y")).unwrap(),
+            "other: 'o';\n"
+        );
+        std::fs::remove_dir_all(scratch).unwrap();
+    }
+
+    #[test]
+    fn include_cycles_and_repeats() {
+        let mut source_cache = SourceCache::new();
+        let (session, root) = grammar_source(&mut source_cache);
+        let mut diagnostics: SimpleDiagnostics<Yacc> = SimpleDiagnostics::default();
+        let provider = MemorySources::new()
+            .with("inc/a.y", "%include b.y\n")
+            .with("inc/b.y", "%include ../grammar.y\n");
+        let mut tool_env = ToolInitEnv {
+            emitter: DiagnosticsEmitter::new(Yacc, &mut diagnostics),
+            source_cache: &mut source_cache,
+            session,
+            include_provider: Some(Box::new(provider)),
+        };
+        let a = tool_env.include(root, "./inc/a.y").unwrap();
+        let b = tool_env.include(a, "b.y").unwrap();
+        assert_eq!(
+            tool_env.source_cache.path_for_id(b),
+            Some(path::Path::new("inc/b.y"))
+        );
+        match tool_env.include(b, "../grammar.y") {
+            Err(e @ IncludeError::Cycle(_)) => assert_eq!(
+                e.to_string(),
+                "Include cycle: grammar.y -> inc/a.y -> inc/b.y -> grammar.y"
+            ),
+            r => panic!("expected a cycle, got {r:?}"),
+        }
+        match tool_env.include(b, "a.y") {
+            Err(IncludeError::Cycle(chain)) => assert_eq!(chain.len(), 3),
+            r => panic!("expected a cycle, got {r:?}"),
+        }
+        match tool_env.include(root, "inc/../inc/b.y") {
+            Err(IncludeError::AlreadyIncluded { src_id, .. }) => assert_eq!(src_id, b),
+            r => panic!("expected a repeated include, got {r:?}"),
+        }
+        match tool_env.include(root, "missing.y") {
+            Err(IncludeError::Io { path, source }) => {
+                assert_eq!(path, path::Path::new("missing.y"));
+                assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+            }
+            r => panic!("expected a missing include, got {r:?}"),
+        }
+        let session = tool_env.finish();
+        assert_eq!(session.included_source_ids(), [a, b]);
+        assert_eq!(session.included_from(b), Some(a));
+        assert_eq!(session.included_from(a), Some(root));
+    }
+
+    #[test]
+    fn dependency_graph() {
+        let mut source_cache = SourceCache::new();
+        let (session, root) = grammar_source(&mut source_cache);
+        let mut diagnostics: SimpleDiagnostics<Yacc> = SimpleDiagnostics::default();
+        let mut tool_env = ToolInitEnv {
+            emitter: DiagnosticsEmitter::new(Yacc, &mut diagnostics),
+            source_cache: &mut source_cache,
+            session,
+            include_provider: Some(Box::new(MemorySources::new().with("tokens.y", ""))),
+        };
+        let tokens = tool_env.include(root, "tokens.y").unwrap();
+        let generated = tool_env.source_cache.add_source(
+            &mut tool_env.session,
+            "grammar.rs".into(),
+            String::new(),
+            YaccSourceKind::YaccRustSourceOutput,
+        );
+        tool_env.session.add_generated_from(generated, root);
+        let mut session = tool_env.finish();
+        session.suggestions = [(root, vec![replace(0, 3, "baz")])].into();
+        let fixed = session
+            .apply_fixes(&mut source_cache, root)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            session.dependencies(root).collect::<Vec<_>>(),
+            [&Dependency {
+                dependent: root,
+                dependency: tokens,
+                kind: DependencyKind::Include
+            }]
+        );
+        assert_eq!(session.transitive_dependencies(generated), [root, tokens]);
+        assert_eq!(session.transitive_dependents(tokens), [root, generated, fixed]);
+        assert_eq!(
+            session
+                .dependents(root)
+                .map(|edge| edge.kind)
+                .collect::<Vec<_>>(),
+            [DependencyKind::Generated, DependencyKind::Fixed]
+        );
+        assert_eq!(session.fixed_from(fixed), Some(root));
+        assert_eq!(session.included_from(tokens), Some(root));
+        assert_eq!(
+            session.topological_order().unwrap(),
+            [tokens, root, generated, fixed]
+        );
+        session.add_generated_from(tokens, generated);
+        assert_eq!(session.topological_order(), None);
+    }
+
+    #[test]
+    fn source_ids_per_cache() {
+        let mut source_cache = SourceCache::new();
+        let (mut session, root) = grammar_source(&mut source_cache);
+        let generated = source_cache.add_source(
+            &mut session,
+            "grammar.rs".into(),
+            String::new(),
+            YaccSourceKind::YaccRustSourceOutput,
+        );
+        session.add_generated_from(generated, root);
+        session.suggestions = [(root, vec![replace(0, 3, "baz")])].into();
+
+        let mut other_cache = SourceCache::new();
+        let (_, other_root) = grammar_source(&mut other_cache);
+        let debug = |src_id: SourceId| format!("{src_id:?}");
+        assert_eq!(debug(root), "SourceId(0)");
+        assert_eq!(debug(generated), "SourceId(1)");
+        assert_eq!(debug(other_root), "SourceId(0)");
+        assert_eq!(root.cache_id(), generated.cache_id());
+        assert_ne!(root.cache_id(), other_root.cache_id());
+        assert_ne!(root, other_root);
+        assert_eq!(other_cache.source_for_id(root), None);
+        assert_eq!(source_cache.source_for_id(other_root), None);
+
+        let mapping = other_cache.merge(source_cache);
+        assert_eq!(mapping.len(), 2);
+        assert_eq!(debug(mapping[&root]), "SourceId(1)");
+        assert_eq!(debug(mapping[&generated]), "SourceId(2)");
+        assert_eq!(mapping[&root].cache_id(), other_root.cache_id());
+        assert_eq!(other_cache.source_for_id(root), None);
+        assert_eq!(other_cache.source_for_id(mapping[&root]), Some(GRAMMAR));
+        assert_eq!(
+            other_cache.source_ids().collect::<Vec<_>>(),
+            [other_root, mapping[&root], mapping[&generated]]
+        );
+
+        session.remap_source_ids(&mapping);
+        assert_eq!(
+            session.added_source_ids(),
+            [mapping[&root], mapping[&generated]]
+        );
+        assert_eq!(
+            session.transitive_dependencies(mapping[&generated]),
+            [mapping[&root]]
+        );
+        assert_eq!(session.suggestions(mapping[&root]).len(), 1);
+        assert!(session.suggestions(root).is_empty());
+
+        // Ids written to disk are the same whichever cache allocated them.
+        #[cfg(feature = "cache")]
+        assert_eq!(root.raw(), other_root.raw());
+
+        // Sources loaded after merging are deduplicated against the merged ones.
+        let mut deduped = SourceCache::new().with_dedup(Dedup::SourceIds);
+        let mut loading = SourceCache::new().with_dedup(Dedup::SourceIds);
+        let loaded = loading.load("grammar.y".into(), GRAMMAR.to_string());
+        let mapping = deduped.merge(loading);
+        assert_eq!(
+            deduped.load("grammar.y".into(), GRAMMAR.to_string()),
+            mapping[&loaded]
+        );
+    }
+
+    #[test]
+    fn depfiles_list_files_read() {
+        let (scratch, dir) = scratch_dir("depfiles");
+        dir.write("a.y", GRAMMAR).unwrap();
+        dir.write("my grammar.y", GRAMMAR).unwrap();
+        let mut source_cache = SourceCache::new();
+        let driver = run_yacc(
+            &mut source_cache,
+            DefaultDriverOptionalArgs {
+                named_string: Some(("named.y".into(), GRAMMAR.to_string())),
+                read_source: Some(("a.y".into(), dir.try_clone().unwrap())),
+                read_stdin: Some(StdinSource::from_reader(GRAMMAR.as_bytes())),
+                provided_sources: vec![
+                    ProvidedSources::new(MemorySources::new().with("memory.y", GRAMMAR))
+                        .with("memory.y"),
+                ],
+                read_sources: vec![InputSources::new(dir).with("*.y")],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let session = &driver.session;
+        assert_eq!(session.loaded_source_ids().len(), 6);
+        assert_eq!(
+            session.file_dependencies(),
+            [path::Path::new("a.y"), path::Path::new("my grammar.y")]
+        );
+        assert_eq!(
+            rerun_if_changed(session, path::Path::new("")),
+            "cargo:rerun-if-changed=a.y\ncargo:rerun-if-changed=my grammar.y\n"
+        );
+        // The dir is not the current directory, so the paths are joined to its path.
+        let root = scratch.display();
+        assert_eq!(
+            rerun_if_changed(session, &scratch),
+            format!("cargo:rerun-if-changed={root}/a.y\ncargo:rerun-if-changed={root}/my grammar.y\n")
+        );
+        assert_eq!(
+            make_depfile(session, path::Path::new("gen"), path::Path::new("out/$grammar.rs")),
+            "out/$$grammar.rs: \\\n  gen/a.y \\\n  gen/my\\ grammar.y\n\ngen/a.y:\n\ngen/my\\ grammar.y:\n"
+        );
+        assert_eq!(
+            ninja_depfile(session, path::Path::new("gen"), path::Path::new("grammar.rs")),
+            "grammar.rs: \\\n  gen/a.y \\\n  gen/my\\ grammar.y\n"
+        );
+        std::fs::remove_dir_all(scratch).unwrap();
+    }
+
+    #[test]
+    fn build_script_diagnostics() {
+        let mut source_cache = SourceCache::new();
+        let (session, src_id) = grammar_source(&mut source_cache);
+        let mut diagnostics: BuildScriptDiagnostics<Yacc, Vec<u8>> =
+            BuildScriptDiagnostics::new(Vec::new());
+        let warning = || YaccGrammarWarning {
+            source_id: None,
+            kind: YaccGrammarWarningKind::Testing(vec![]),
+            suggestions: vec![],
+        };
+        diagnostics.emit_warning(warning());
+        assert_eq!(diagnostics.failure_summary(), None);
+        diagnostics.emit_error(duplicate_rule_error(src_id, vec![Span::new(20, 23)]));
+        diagnostics.no_more_data(&DiagnosticsEnv::new(&source_cache, &session));
+        assert_eq!(
+            diagnostics.failure_summary().as_deref(),
+            Some("yacc failed with 1 error and 1 warning")
+        );
+        // Later drivers only summarize their own diagnostics.
+        diagnostics.emit_warning(warning());
+        diagnostics.no_more_data(&DiagnosticsEnv::new(&source_cache, &session));
+        diagnostics.emit_error(duplicate_rule_error(src_id, vec![Span::new(20, 23)]));
+        diagnostics.no_more_data(&DiagnosticsEnv::new(&source_cache, &session));
+        assert_eq!(
+            diagnostics.failure_summary().as_deref(),
+            Some("yacc failed with 2 errors and 2 warnings")
+        );
+        let out = String::from_utf8(diagnostics.into_writer()).unwrap();
+        assert!(out.lines().all(|line| line.starts_with("cargo:warning=")));
+        assert!(!out.contains('\x1b'));
+        assert_eq!(
+            out,
+            "cargo:warning=warning: Yacc warning test\n\
+             cargo:warning=error[Y0001]: Duplicate rule\n\
+             cargo:warning= --> grammar.y:3:1\n\
+             cargo:warning=  |\n\
+             cargo:warning=3 | foo: 'c';\n\
+             cargo:warning=  | ^^^\n\
+             cargo:warning=error: yacc failed with 1 error and 1 warning\n\
+             cargo:warning=warning: Yacc warning test\n\
+             cargo:warning=error[Y0001]: Duplicate rule\n\
+             cargo:warning= --> grammar.y:3:1\n\
+             cargo:warning=  |\n\
+             cargo:warning=3 | foo: 'c';\n\
+             cargo:warning=  | ^^^\n\
+             cargo:warning=error: yacc failed with 1 error\n"
+        );
+    }
+
+    /// A tool counting its runs, which emits an error for each loaded source.
+    #[derive(Copy, Clone)]
+    struct Counted;
+
+    thread_local! {
+        static COUNTED_RUNS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    #[derive(Clone)]
+    struct CountedOutput {
+        runs: usize,
+    }
+
+    impl IncrementalOutput for CountedOutput {}
+
+    impl Tool for Counted {
+        type Error = LexError;
+        type Warning = NeverWarnings;
+        type Output = CountedOutput;
+        type SourceKind = LexSourceKind;
+    }
+
+    impl Args for Counted {
+        type RequiredArgs = u32;
+        type OptionalArgs = ();
+    }
+
+    impl ToolInit<Counted> for CountedOutput {
+        fn tool_init<D: Diagnostics<Counted>>(
+            _config: Params<Counted>,
+            tool_env: &mut ToolInitEnv<Counted, D>,
+        ) -> Self {
+            let runs = COUNTED_RUNS.with(|runs| runs.get() + 1);
+            COUNTED_RUNS.with(|cell| cell.set(runs));
+            for src_id in tool_env.session.loaded_source_ids().to_vec() {
+                tool_env.include(src_id, "tokens.l").unwrap();
+                tool_env.emitter.emit_non_fatal_error(LexError {
+                    source_id: Some(src_id),
+                    kind: LexErrorKind::Testing(vec![Span::new(0, 1)]),
+                });
+                let generated = tool_env.source_cache.add_source(
+                    &mut tool_env.session,
+                    "lexer.rs".into(),
+                    format!("// run {runs}"),
+                    LexSourceKind::LexRustSourceOutput,
+                );
+                tool_env.session.add_generated_from(generated, src_id);
+            }
+            CountedOutput { runs }
+        }
+    }
+
+    #[test]
+    fn incremental_reruns() {
+        let mut source_cache = SourceCache::new();
+        let mut state = IncrementalState::new();
+        let mut run = |arg: u32, source: &str, tokens: &str| {
+            let mut diagnostics: QuickfixDiagnostics<Counted, Vec<u8>> =
+                QuickfixDiagnostics::new(vec![]);
+            let driver = Driver {
+                tool: Counted,
+                driver: DefaultDriver,
+                driver_args: (
+                    DefaultDriverArgs {},
+                    DefaultDriverOptionalArgs {
+                        named_string: Some(("lexer.l".into(), source.to_string())),
+                        include_provider: Some(Box::new(
+                            MemorySources::new().with("tokens.l", tokens),
+                        )),
+                        ..Default::default()
+                    },
+                ),
+                tool_args: (arg, ()),
+            }
+            .driver_init_incremental(
+                DefaultDriverEnv {
+                    source_cache: &mut source_cache,
+                    diagnostics: &mut diagnostics,
+                    tool: Counted,
+                },
+                &mut state,
+            )
+            .unwrap();
+            let out = String::from_utf8(diagnostics.into_writer()).unwrap();
+            assert_eq!(out, "lexer.l:1:1: error: Lex error test\n");
+            assert_eq!(driver.session.included_source_ids().len(), 1);
+            (driver.output.runs, state.was_reused())
+        };
+        let first = run(1, "a", "t").0;
+        assert_eq!(run(1, "a", "t"), (first, true));
+        assert_eq!(run(2, "a", "t"), (first + 1, false));
+        assert_eq!(run(2, "a", "t2"), (first + 2, false));
+        assert_eq!(run(2, "b", "t2"), (first + 3, false));
+        assert_eq!(run(2, "b", "t2"), (first + 3, true));
+        // Only the sources of the runs which called `tool_init` were added.
+        assert_eq!(source_cache.source_ids().count(), 12);
+    }
+
+    #[test]
+    fn include_defaults_to_read_dir() {
+        let (scratch, dir) = scratch_dir("include-default");
+        dir.create_dir_all("lex").unwrap();
+        dir.write("lex/lexer.l", "a").unwrap();
+        dir.write("lex/tokens.l", "t").unwrap();
+        let read_source = DefaultDriverOptionalArgs {
+            read_source: Some(("lex/lexer.l".into(), dir.try_clone().unwrap())),
+            ..Default::default()
+        };
+        let read_sources = DefaultDriverOptionalArgs {
+            read_sources: vec![InputSources::new(dir).with("lex/lexer.l")],
+            ..Default::default()
+        };
+        for optional in [read_source, read_sources] {
+            let mut source_cache = SourceCache::new();
+            let mut diagnostics: SimpleDiagnostics<Counted> = SimpleDiagnostics::default();
+            let driver = Driver {
+                tool: Counted,
+                driver: DefaultDriver,
+                driver_args: (DefaultDriverArgs {}, optional),
+                tool_args: (1, ()),
+            }
+            .driver_init(DefaultDriverEnv {
+                source_cache: &mut source_cache,
+                diagnostics: &mut diagnostics,
+                tool: Counted,
+            })
+            .unwrap();
+            let [tokens] = driver.session.included_source_ids() else {
+                panic!("expected a single include");
+            };
+            assert_eq!(
+                source_cache.path_for_id(*tokens),
+                Some(path::Path::new("lex/tokens.l"))
+            );
+            assert_eq!(source_cache.source_for_id(*tokens), Some("t"));
+        }
+        std::fs::remove_dir_all(scratch).unwrap();
+    }
+
+    #[cfg(feature = "cache")]
+    impl Encode for LexError {
+        fn encode(&self, encoder: &mut Encoder) {
+            self.source_id.encode(encoder);
+            let LexErrorKind::Testing(spans) = &self.kind;
+            spans.encode(encoder);
+        }
+    }
+
+    #[cfg(feature = "cache")]
+    impl Decode for LexError {
+        fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+            Ok(LexError {
+                source_id: Decode::decode(decoder)?,
+                kind: LexErrorKind::Testing(Decode::decode(decoder)?),
+            })
+        }
+    }
+
+    #[cfg(feature = "cache")]
+    impl Encode for NeverWarnings {
+        fn encode(&self, _encoder: &mut Encoder) {
+            unreachable!()
+        }
+    }
+
+    #[cfg(feature = "cache")]
+    impl Decode for NeverWarnings {
+        fn decode(_decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+            Err(DecodeError::Invalid("NeverWarnings"))
+        }
+    }
+
+    #[cfg(feature = "cache")]
+    impl Encode for LexSourceKind {
+        fn encode(&self, encoder: &mut Encoder) {
+            encoder.write_u8(match self {
+                LexSourceKind::LexSourceInput => 0,
+                LexSourceKind::LexRustSourceOutput => 1,
+            });
+        }
+    }
+
+    #[cfg(feature = "cache")]
+    impl Decode for LexSourceKind {
+        fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+            match decoder.read_u8()? {
+                0 => Ok(LexSourceKind::LexSourceInput),
+                1 => Ok(LexSourceKind::LexRustSourceOutput),
+                tag => Err(DecodeError::InvalidTag(tag)),
+            }
+        }
+    }
+
+    #[cfg(feature = "cache")]
+    impl Encode for CountedOutput {
+        fn encode(&self, encoder: &mut Encoder) {
+            self.runs.encode(encoder);
+        }
+    }
+
+    #[cfg(feature = "cache")]
+    impl Decode for CountedOutput {
+        fn decode(decoder: &mut Decoder<'_>) -> Result<Self, DecodeError> {
+            let runs = Decode::decode(decoder)?;
+            Ok(CountedOutput { runs })
+        }
+    }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn disk_cache() {
+        let (path, cache_dir) = scratch_dir("disk-cache");
+        let run_with = |cache: &DiskCache<Counted>, lexer: &str, tokens: &str| {
+            // A new `SourceCache` for each run, as in separate processes.
+            let mut source_cache = SourceCache::new();
+            let mut diagnostics: QuickfixDiagnostics<Counted, Vec<u8>> =
+                QuickfixDiagnostics::new(vec![]);
+            let driver = Driver {
+                tool: Counted,
+                driver: DefaultDriver,
+                driver_args: (
+                    DefaultDriverArgs {},
+                    DefaultDriverOptionalArgs {
+                        named_string: Some(("lexer.l".into(), lexer.to_string())),
+                        include_provider: Some(Box::new(
+                            MemorySources::new().with("tokens.l", tokens),
+                        )),
+                        ..Default::default()
+                    },
+                ),
+                tool_args: (1, ()),
+            }
+            .driver_init_cached(
+                DefaultDriverEnv {
+                    source_cache: &mut source_cache,
+                    diagnostics: &mut diagnostics,
+                    tool: Counted,
+                },
+                cache,
+            )
+            .unwrap();
+            let out = String::from_utf8(diagnostics.into_writer()).unwrap();
+            assert_eq!(out, "lexer.l:1:1: error: Lex error test\n");
+            let session = &driver.session;
+            let loaded = session.loaded_source_ids()[0];
+            let included = session.included_source_ids()[0];
+            let generated = session.added_source_ids()[0];
+            assert_eq!(source_cache.source_for_id(included), Some(tokens));
+            assert_eq!(
+                session.transitive_dependencies(generated),
+                [loaded, included]
+            );
+            let generated_src = source_cache.source_for_id(generated).unwrap();
+            assert_eq!(generated_src, format!("// run {}", driver.output.runs));
+            (driver.output.runs, driver.cache_status.unwrap(), driver.cache_warning)
+        };
+        let cache = DiskCache::<Counted>::new(cache_dir.try_clone().unwrap());
+        let run = |tokens: &str| {
+            let (runs, status, warning) = run_with(&cache, "a", tokens);
+            assert!(warning.is_none());
+            (runs, status)
+        };
+        let (first, status) = run("t");
+        assert_eq!(status, CacheStatus::Miss);
+        assert_eq!(run("t"), (first, CacheStatus::Hit));
+        assert_eq!(COUNTED_RUNS.with(|runs| runs.get()), first);
+        assert_eq!(run("t2"), (first + 1, CacheStatus::Miss));
+        assert_eq!(run("t2"), (first + 1, CacheStatus::Hit));
+        assert_eq!(cache.prune(std::time::Duration::from_secs(3600)).unwrap(), 0);
+        assert_eq!(cache.prune(std::time::Duration::ZERO).unwrap(), 1);
+        assert_eq!(run("t2"), (first + 2, CacheStatus::Miss));
+
+        // An entry for other sources found at the path of a run, as if their keys
+        // collided, is not used.
+        let entries = || {
+            let entries = std::fs::read_dir(path.join(Counted::name())).unwrap();
+            entries.map(|entry| entry.unwrap().path()).collect::<Vec<_>>()
+        };
+        let [other_entry] = &entries()[..] else {
+            panic!("expected a single entry");
+        };
+        let (runs, status, _) = run_with(&cache, "b", "t2");
+        assert_eq!((runs, status), (first + 3, CacheStatus::Miss));
+        let entry = entries().into_iter().find(|entry| entry != other_entry).unwrap();
+        std::fs::copy(other_entry, &entry).unwrap();
+        let (runs, status, _) = run_with(&cache, "b", "t2");
+        assert_eq!((runs, status), (first + 4, CacheStatus::Miss));
+
+        // Failing to write an entry keeps the output of the run.
+        let readonly = DirView::open_ambient_dir(
+            &path,
+            dir_view::ViewKind::Readonly,
+            cap_std::ambient_authority(),
+        )
+        .unwrap();
+        let (runs, status, warning) = run_with(&DiskCache::new(readonly), "a", "t3");
+        assert_eq!((runs, status), (first + 5, CacheStatus::Miss));
+        assert!(matches!(warning, Some(DriverError::Write { .. })));
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[cfg(all(feature = "watch", target_os = "linux"))]
+    #[test]
+    fn watch_reruns_on_change() {
+        let (scratch, dir) = scratch_dir("watch");
+        std::fs::write(scratch.join("lexer.l"), "a").unwrap();
+        std::fs::write(scratch.join("tokens.l"), "t").unwrap();
+        let watch = Watch::new(&scratch).debounce(std::time::Duration::from_millis(50));
+        let cancel = watch.cancel_handle();
+        let editor = {
+            let (scratch, cancel) = (scratch.clone(), cancel.clone());
+            std::thread::spawn(move || {
+                let pause = || std::thread::sleep(std::time::Duration::from_millis(300));
+                pause();
+                // A burst of writes re-runs once.
+                std::fs::write(scratch.join("tokens.l"), "t1").unwrap();
+                std::fs::write(scratch.join("tokens.l"), "t2").unwrap();
+                pause();
+                // Replaced by renaming over it, as editors do.
+                std::fs::write(scratch.join(".lexer.l.swp"), "b").unwrap();
+                std::fs::rename(scratch.join(".lexer.l.swp"), scratch.join("lexer.l")).unwrap();
+                // Unrelated files are ignored.
+                std::fs::write(scratch.join("notes.txt"), "n").unwrap();
+                pause();
+                pause();
+                // Don't hang if a change was missed.
+                cancel.cancel();
+            })
+        };
+        let mut source_cache = SourceCache::new();
+        let mut seen = vec![];
+        let last = watch
+            .run(&mut source_cache, |source_cache| {
+                let mut diagnostics: QuickfixDiagnostics<Counted, Vec<u8>> =
+                    QuickfixDiagnostics::new(vec![]);
+                let driver = Driver {
+                    tool: Counted,
+                    driver: DefaultDriver,
+                    driver_args: (
+                        DefaultDriverArgs {},
+                        DefaultDriverOptionalArgs {
+                            read_source: Some(("lexer.l".into(), dir.try_clone()?)),
+                            include_provider: Some(Box::new(dir.try_clone()?)),
+                            ..Default::default()
+                        },
+                    ),
+                    tool_args: (1, ()),
+                }
+                .driver_init(DefaultDriverEnv {
+                    source_cache: &mut *source_cache,
+                    diagnostics: &mut diagnostics,
+                    tool: Counted,
+                })?;
+                let session = &driver.session;
+                let lexer = session.loaded_source_ids()[0];
+                let tokens = session.included_source_ids()[0];
+                seen.push((
+                    source_cache.source_for_id(lexer).unwrap().to_string(),
+                    source_cache.source_for_id(tokens).unwrap().to_string(),
+                    String::from_utf8(diagnostics.into_writer()).unwrap(),
+                ));
+                if seen.len() == 3 {
+                    cancel.cancel();
+                }
+                Ok(driver)
+            })
+            .unwrap()
+            .unwrap();
+        editor.join().unwrap();
+        let seen = seen
+            .iter()
+            .map(|(lexer, tokens, out)| (lexer.as_str(), tokens.as_str(), out.as_str()))
+            .collect::<Vec<_>>();
+        let diagnostics = "lexer.l:1:1: error: Lex error test\n";
+        assert_eq!(
+            seen,
+            [
+                ("a", "t", diagnostics),
+                ("a", "t2", diagnostics),
+                ("b", "t2", diagnostics),
+            ]
+        );
+        // Only the sources of the last run remain.
+        assert_eq!(source_cache.source_ids().count(), 3);
+        assert_eq!(last.session.file_dependencies().len(), 2);
+    }
 }
@@ -1,8 +1,9 @@
+use crate::code::ErrorCode;
 use crate::diagnostics::Diagnostics;
 use crate::driver::ToolInitEnv;
 use crate::source::SourceArtifact;
 
-use crate::{Args, Params, Spanned};
+use crate::{short_type_name, Args, Params, Spanned};
 use std::error;
 
 /// Tool specific types and their bounds.
@@ -10,6 +11,20 @@ pub trait Tool: Args
 where
     Self: Sized + Copy,
 {
+    /// The name of the tool as shown in reports.
+    ///
+    /// Defaults to the name of the implementing type, without its module path.
+    fn name() -> &'static str {
+        short_type_name::<Self>()
+    }
+    /// The version of the tool, e.g. `env!("CARGO_PKG_VERSION")`.
+    ///
+    /// Part of the key of `DiskCache` entries, so that entries
+    /// written by other versions are not used.
+    const VERSION: &'static str = "";
+    /// The codes which errors and warnings of the tool may carry,
+    /// along with their explanations.
+    const ERROR_CODES: &'static [ErrorCode] = &[];
     /// The type of errors specific to a tool.
     type Error: SourceArtifact + error::Error + Spanned;
     /// The type of warnings specific to a tool.
@@ -18,7 +33,7 @@ where
     type Output: ToolInit<Self>;
     /// A tool specific kind for source text
     /// accessible from a session.
-    type SourceKind;
+    type SourceKind: 'static;
 }
 
 /// Trait for running a tool.
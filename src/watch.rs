@@ -0,0 +1,200 @@
+use crate::{
+    driver::{DriverError, DriverOutput},
+    source::{Session, SourceCache},
+    tool::Tool,
+};
+use inotify::{Inotify, WatchDescriptor, WatchMask};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often a [Watch] checks for changes and cancellation.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Errors watching the files read by a driver.
+#[derive(thiserror::Error, Debug)]
+pub enum WatchError {
+    #[error("Io error {0} ")]
+    Io(#[from] io::Error),
+    /// The first run failed, so there were no files to watch.
+    #[error("Driver error {0}")]
+    Driver(#[from] DriverError),
+}
+
+/// Cancels a [Watch], from another thread or from within a run.
+#[derive(Debug, Clone, Default)]
+pub struct WatchCancel(Arc<AtomicBool>);
+
+impl WatchCancel {
+    /// Stops the watch once the current run, if any, returns.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Re-runs a driver whenever the files read by its last run change.
+///
+/// The files watched are the [Session::file_dependencies] of the last successful run,
+/// relative to `root`, e.g. the directory the `DirView` they were read through was
+/// opened at. Their directories are watched with inotify, so that files which are
+/// replaced by renaming over them, as many editors do, are still watched.
+pub struct Watch {
+    root: PathBuf,
+    debounce: Duration,
+    cancel: WatchCancel,
+}
+
+impl Watch {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            debounce: Duration::from_millis(100),
+            cancel: WatchCancel::default(),
+        }
+    }
+
+    /// How long to wait after a change for further changes, before re-running.
+    ///
+    /// Defaults to 100ms.
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// A handle which cancels this watch.
+    pub fn cancel_handle(&self) -> WatchCancel {
+        self.cancel.clone()
+    }
+
+    /// Calls `run`, then calls it again each time the files it read change, until cancelled.
+    ///
+    /// `run` is expected to call `driver_init` with the given `SourceCache`, along with a
+    /// new `Diagnostics` to render the diagnostics of that run, so every source is loaded
+    /// again. Once a run succeeds, the sources of the previous successful run are removed
+    /// from the `SourceCache`, unless the new run shares them through [Dedup::SourceIds](crate::Dedup::SourceIds) or
+    /// `driver_init_incremental`. `run` should report its own
+    /// errors, a failed run leaves the files of the previous run watched, unless there
+    /// was none in which case the error is returned.
+    ///
+    /// Returns the output of the last successful run once cancelled.
+    pub fn run<X: Tool>(
+        &self,
+        source_cache: &mut SourceCache,
+        mut run: impl FnMut(&mut SourceCache) -> Result<DriverOutput<X>, DriverError>,
+    ) -> Result<Option<DriverOutput<X>>, WatchError> {
+        let mut inotify = Inotify::init()?;
+        let mut watched = HashMap::new();
+        let mut last: Option<DriverOutput<X>> = None;
+        loop {
+            match run(source_cache) {
+                Ok(output) => {
+                    if let Some(previous) = last.take() {
+                        evict(source_cache, &previous.session);
+                    }
+                    watched =
+                        self.rewatch(&mut inotify, watched, output.session.file_dependencies())?;
+                    last = Some(output);
+                }
+                Err(e) if last.is_none() => return Err(e.into()),
+                Err(_) => (),
+            }
+            if !self.wait_for_change(&mut inotify, &watched)? {
+                return Ok(last);
+            }
+        }
+    }
+
+    /// Replaces the watches of `watched` with watches on the directories of `files`.
+    fn rewatch(
+        &self,
+        inotify: &mut Inotify,
+        watched: HashMap<WatchDescriptor, HashSet<OsString>>,
+        files: &[PathBuf],
+    ) -> io::Result<HashMap<WatchDescriptor, HashSet<OsString>>> {
+        for wd in watched.into_keys() {
+            // The watch is already gone if its directory was removed.
+            let _ = inotify.watches().remove(wd);
+        }
+        let mut by_dir: HashMap<PathBuf, HashSet<OsString>> = HashMap::new();
+        for file in files {
+            let path = self.root.join(file);
+            let Some(name) = path.file_name() else {
+                continue;
+            };
+            let dir = path.parent().unwrap_or(Path::new("."));
+            by_dir
+                .entry(dir.to_path_buf())
+                .or_default()
+                .insert(name.to_os_string());
+        }
+        let mask = WatchMask::MODIFY
+            | WatchMask::CLOSE_WRITE
+            | WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO;
+        let mut watched: HashMap<WatchDescriptor, HashSet<OsString>> = HashMap::new();
+        for (dir, names) in by_dir {
+            // Directories which are the same through a link share a descriptor.
+            let wd = inotify.watches().add(&dir, mask)?;
+            watched.entry(wd).or_default().extend(names);
+        }
+        Ok(watched)
+    }
+
+    /// Waits until a watched file changed and no further changes occurred for
+    /// the debounce duration. Returns `false` if the watch was cancelled first.
+    fn wait_for_change(
+        &self,
+        inotify: &mut Inotify,
+        watched: &HashMap<WatchDescriptor, HashSet<OsString>>,
+    ) -> io::Result<bool> {
+        let mut buffer = [0; 4096];
+        let mut changed_at: Option<Instant> = None;
+        loop {
+            if self.cancel.is_cancelled() {
+                return Ok(false);
+            }
+            match inotify.read_events(&mut buffer) {
+                Ok(events) => {
+                    for event in events {
+                        let changed = event.name.is_some_and(|name| {
+                            watched
+                                .get(&event.wd)
+                                .is_some_and(|names| names.contains(name))
+                        });
+                        if changed {
+                            changed_at = Some(Instant::now());
+                        }
+                    }
+                    continue;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                Err(e) => return Err(e),
+            }
+            if changed_at.is_some_and(|at| at.elapsed() >= self.debounce) {
+                return Ok(true);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Releases the sources of `previous`, removing those which no other session refers to.
+fn evict<SourceKind>(source_cache: &mut SourceCache, previous: &Session<SourceKind>) {
+    for src_id in previous.all_source_ids() {
+        source_cache.release(src_id);
+    }
+}